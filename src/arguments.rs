@@ -0,0 +1,144 @@
+use jsonrpc_core::serde_json::{json, Value};
+use radix_engine::transaction::AbiProvider;
+use sbor::describe::Type;
+use scrypto::prelude::*;
+
+/// Why the arguments don't fit the ABI, `data` names the argument that doesn't.
+pub struct ArgumentError {
+    pub message: String,
+    pub data: Option<Value>,
+}
+
+/// Checks the arguments of a function call against the blueprint's ABI. `received` are the
+/// arguments as the client sent them, before any `@name` was resolved, errors show those.
+pub fn check_function_args<A: AbiProvider>(
+    abi_provider: &A,
+    package: Address,
+    blueprint: &str,
+    function: &str,
+    args: &[String],
+    received: &[String],
+) -> Result<(), ArgumentError> {
+    let abi = abi_provider
+        .export_abi(package, blueprint, false)
+        .map_err(|e| error(format!("Can't get the ABI of {}: {}", blueprint, e)))?;
+    let function = abi
+        .functions
+        .iter()
+        .find(|f| f.name == function)
+        .ok_or_else(|| error(format!("{} has no function {}", blueprint, function)))?;
+    check_args(&function.inputs, args, received)
+}
+
+/// Checks the arguments of a method call against the ABI of the component's blueprint.
+pub fn check_method_args<A: AbiProvider>(
+    abi_provider: &A,
+    component: Address,
+    method: &str,
+    args: &[String],
+    received: &[String],
+) -> Result<(), ArgumentError> {
+    let abi = abi_provider
+        .export_abi_by_component(component, false)
+        .map_err(|e| error(format!("Can't get the ABI of {}: {}", component, e)))?;
+    let method = abi
+        .methods
+        .iter()
+        .find(|m| m.name == method)
+        .ok_or_else(|| error(format!("{} has no method {}", abi.name, method)))?;
+    check_args(&method.inputs, args, received)
+}
+
+fn check_args(inputs: &[Type], args: &[String], received: &[String]) -> Result<(), ArgumentError> {
+    if args.len() != inputs.len() {
+        // Point at the first argument that is missing or too many
+        let index = args.len().min(inputs.len());
+        return Err(ArgumentError {
+            message: format!("Expected {} arguments, got {}", inputs.len(), args.len()),
+            data: Some(json!({
+                "index": index,
+                "expected": inputs.get(index).map(type_name),
+                "received": received.get(index)
+            })),
+        });
+    }
+
+    for (index, (ty, arg)) in inputs.iter().zip(args).enumerate() {
+        if !fits(ty, arg) {
+            return Err(ArgumentError {
+                message: format!("Argument {} isn't a {}", index, type_name(ty)),
+                data: Some(json!({
+                    "index": index,
+                    "expected": type_name(ty),
+                    "received": received.get(index)
+                })),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Whether the transaction builder can turn the string into a value of this type. Types it
+/// doesn't parse from strings are left for the builder to reject.
+fn fits(ty: &Type, arg: &str) -> bool {
+    match ty {
+        Type::Bool => arg.parse::<bool>().is_ok(),
+        Type::I8 => arg.parse::<i8>().is_ok(),
+        Type::I16 => arg.parse::<i16>().is_ok(),
+        Type::I32 => arg.parse::<i32>().is_ok(),
+        Type::I64 => arg.parse::<i64>().is_ok(),
+        Type::I128 => arg.parse::<i128>().is_ok(),
+        Type::U8 => arg.parse::<u8>().is_ok(),
+        Type::U16 => arg.parse::<u16>().is_ok(),
+        Type::U32 => arg.parse::<u32>().is_ok(),
+        Type::U64 => arg.parse::<u64>().is_ok(),
+        Type::U128 => arg.parse::<u128>().is_ok(),
+        Type::String => true,
+        Type::Custom { name, .. } => match custom_name(name) {
+            "Decimal" => Decimal::from_str(arg).is_ok(),
+            "BigDecimal" => BigDecimal::from_str(arg).is_ok(),
+            "Address" => Address::from_str(arg).is_ok(),
+            "H256" => H256::from_str(arg).is_ok(),
+            // Buckets and their refs are given as "amount,resource_address"
+            "Bucket" | "BucketRef" => match arg.split_once(',') {
+                Some((amount, resource)) => {
+                    Decimal::from_str(amount).is_ok() && Address::from_str(resource).is_ok()
+                }
+                None => false,
+            },
+            _ => true,
+        },
+        _ => true,
+    }
+}
+
+fn type_name(ty: &Type) -> String {
+    match ty {
+        Type::Bool => "bool".to_string(),
+        Type::I8 => "i8".to_string(),
+        Type::I16 => "i16".to_string(),
+        Type::I32 => "i32".to_string(),
+        Type::I64 => "i64".to_string(),
+        Type::I128 => "i128".to_string(),
+        Type::U8 => "u8".to_string(),
+        Type::U16 => "u16".to_string(),
+        Type::U32 => "u32".to_string(),
+        Type::U64 => "u64".to_string(),
+        Type::U128 => "u128".to_string(),
+        Type::String => "String".to_string(),
+        Type::Custom { name, .. } => custom_name(name).to_string(),
+        ty => format!("{:?}", ty),
+    }
+}
+
+/// `scrypto::types::Decimal` becomes `Decimal`.
+fn custom_name(name: &str) -> &str {
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+fn error(message: String) -> ArgumentError {
+    ArgumentError {
+        message,
+        data: None,
+    }
+}
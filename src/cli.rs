@@ -0,0 +1,84 @@
+use clap::Parser;
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// Command line options, the server options can also be set through environment variables.
+#[derive(Parser, Clone)]
+#[clap(
+    name = "rad-rpc",
+    about = "JSON-RPC server for a local Radix Engine ledger"
+)]
+pub struct Args {
+    /// Setup file with the packages, components and manifests to load on start
+    #[clap(long, env = "RAD_RPC_CONFIG", default_value = "Config.json")]
+    pub config: PathBuf,
+
+    /// Address the HTTP server listens on
+    #[clap(long, env = "RAD_RPC_BIND", default_value = "127.0.0.1:3030")]
+    pub bind: SocketAddr,
+
+    /// Number of HTTP server threads
+    #[clap(long, env = "RAD_RPC_THREADS", default_value = "4")]
+    pub threads: usize,
+
+    /// Origin allowed to call the HTTP server, repeat for several, `*` allows any
+    #[clap(
+        long,
+        env = "RAD_RPC_CORS_ORIGIN",
+        default_value = "*",
+        multiple_occurrences = true,
+        use_value_delimiter = true
+    )]
+    pub cors_origin: Vec<String>,
+
+    /// Port of the WebSocket server, on the same host as --bind, 0 picks a free one
+    #[clap(long, env = "RAD_RPC_WS_PORT", default_value = "3031")]
+    pub ws_port: u16,
+
+    /// Don't start the WebSocket server
+    #[clap(long, env = "RAD_RPC_NO_WS")]
+    pub no_ws: bool,
+
+    /// Port of the line delimited TCP server, on the same host as --bind, 0 picks a free one
+    #[clap(long, env = "RAD_RPC_TCP_PORT", default_value = "3032")]
+    pub tcp_port: u16,
+
+    /// Don't start the TCP server
+    #[clap(long, env = "RAD_RPC_NO_TCP")]
+    pub no_tcp: bool,
+
+    /// Also serve the methods over a Unix domain socket at this path
+    #[clap(long, env = "RAD_RPC_IPC_PATH")]
+    pub ipc_path: Option<String>,
+
+    /// Keep the ledger in this directory and resume from it on start
+    #[clap(long, env = "RAD_RPC_LEDGER_DIR")]
+    pub ledger_dir: Option<PathBuf>,
+
+    /// Trace transactions that don't say whether they want to be traced
+    #[clap(long, env = "RAD_RPC_TRACE")]
+    pub trace: bool,
+
+    /// Start with the bootstrapped ledger only, without running the setup file
+    #[clap(long, env = "RAD_RPC_NO_SETUP")]
+    pub no_setup: bool,
+
+    /// Write an example setup file to the --config path and exit
+    #[clap(long)]
+    pub write_example_config: bool,
+
+    /// Serve JSON-RPC over stdin and stdout instead of the network, logging to stderr
+    #[clap(long)]
+    pub stdio: bool,
+}
+
+impl Args {
+    /// Address of a server on the same host as the HTTP server, None when it is turned off.
+    pub fn with_port(&self, port: u16, off: bool) -> Option<SocketAddr> {
+        if off {
+            return None;
+        }
+        Some(SocketAddr::new(self.bind.ip(), port))
+    }
+}
@@ -1,11 +1,23 @@
-use radix_engine::ledger::InMemoryLedger;
-use radix_engine::transaction::TransactionExecutor;
+use super::persist;
+
+use std::io;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PersistedConfig {
+    epoch: usize,
+    nonce: usize,
+}
+
 pub struct Config {
     pub nonce: AtomicUsize,
     pub epoch: AtomicUsize,
     pub updated: AtomicBool,
+    // Default for transactions that don't say whether they want to be traced
+    pub trace: AtomicBool,
+    // When set the epoch and nonce are written here every time they change
+    path: Option<PathBuf>,
 }
 
 impl Config {
@@ -14,19 +26,61 @@ impl Config {
             nonce: AtomicUsize::new(0),
             epoch: AtomicUsize::new(0),
             updated: AtomicBool::new(false),
+            trace: AtomicBool::new(false),
+            path: None,
+        }
+    }
+
+    /// Persists the epoch and nonce to `path`, loading the previous values if the file exists.
+    /// Returns true if there was previous state to resume from.
+    pub fn persist_to(&mut self, path: PathBuf) -> io::Result<bool> {
+        let mut resumed = false;
+        if path.exists() {
+            let file = std::fs::File::open(&path)?;
+            let persisted: PersistedConfig =
+                jsonrpc_core::serde_json::from_reader(file).map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Corrupted config {}: {}", path.display(), e),
+                    )
+                })?;
+            self.epoch.store(persisted.epoch, Ordering::SeqCst);
+            self.nonce.store(persisted.nonce, Ordering::SeqCst);
+            resumed = true;
         }
+        self.path = Some(path);
+        Ok(resumed)
     }
 
-    pub fn store_nonce(&mut self, executor: &TransactionExecutor<InMemoryLedger>) {
-        let nonce_save = executor.nonce() as usize;
+    pub fn store_nonce(&mut self, nonce: u64) -> io::Result<()> {
         self.updated.store(true, Ordering::SeqCst);
-        self.nonce.store(nonce_save, Ordering::SeqCst);
+        self.nonce.store(nonce as usize, Ordering::SeqCst);
+        self.save()
     }
 
-    pub fn load_nonce(&mut self) -> (u64, u64) {
+    pub fn load_nonce(&self) -> (u64, u64) {
         let epoch = self.epoch.load(Ordering::SeqCst);
         let nonce = self.nonce.load(Ordering::SeqCst);
 
         (epoch as u64, nonce as u64)
     }
+
+    pub fn restore_nonce(&mut self, epoch: u64, nonce: u64) -> io::Result<()> {
+        self.updated.store(true, Ordering::SeqCst);
+        self.epoch.store(epoch as usize, Ordering::SeqCst);
+        self.nonce.store(nonce as usize, Ordering::SeqCst);
+        self.save()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if let Some(path) = &self.path {
+            let persisted = PersistedConfig {
+                epoch: self.epoch.load(Ordering::SeqCst),
+                nonce: self.nonce.load(Ordering::SeqCst),
+            };
+            let bytes = jsonrpc_core::serde_json::to_vec(&persisted)?;
+            persist::write_atomic(path, &bytes)?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,277 @@
+use jsonrpc_core::serde_json::{self, json, Value};
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+// The setup waits for the webhook, so it must not hang on an unreachable or stuck endpoint
+const WEBHOOK_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where the setup sends the addresses it created, the `export` section of Config.json.
+#[derive(serde::Serialize, serde::Deserialize, Default)]
+pub struct ExportSetup {
+    // Paths of the files to write, each one is left alone when not given
+    #[serde(default)]
+    pub json: Option<String>,
+    #[serde(default)]
+    pub typescript: Option<String>,
+    #[serde(default)]
+    pub env: Option<String>,
+    // Prefix of the .env variable names, use e.g. VITE_ for variables the frontend can see
+    #[serde(default)]
+    pub env_prefix: Option<String>,
+    // URL the addresses are POSTed to as JSON
+    #[serde(default)]
+    pub webhook: Option<String>,
+}
+
+/// Everything the setup named, by name.
+pub struct Addresses {
+    pub packages: BTreeMap<String, String>,
+    pub components: BTreeMap<String, String>,
+    pub resources: BTreeMap<String, String>,
+    pub admin_account: String,
+    pub admin_key: String,
+}
+
+/// Somewhere the addresses can be pushed to.
+pub trait AddressSink {
+    fn push(&self, addresses: &Addresses) -> Result<(), String>;
+}
+
+pub struct JsonFile(pub String);
+pub struct TypeScriptFile(pub String);
+pub struct EnvFile {
+    pub path: String,
+    pub prefix: String,
+}
+pub struct Webhook(pub String);
+
+impl ExportSetup {
+    /// The sinks this export section asks for, in the order they're pushed to.
+    pub fn sinks(&self) -> Vec<Box<dyn AddressSink>> {
+        let mut sinks: Vec<Box<dyn AddressSink>> = Vec::new();
+        if let Some(path) = &self.json {
+            sinks.push(Box::new(JsonFile(path.clone())));
+        }
+        if let Some(path) = &self.typescript {
+            sinks.push(Box::new(TypeScriptFile(path.clone())));
+        }
+        if let Some(path) = &self.env {
+            sinks.push(Box::new(EnvFile {
+                path: path.clone(),
+                prefix: self
+                    .env_prefix
+                    .clone()
+                    .unwrap_or_else(|| "RAD_".to_string()),
+            }));
+        }
+        if let Some(url) = &self.webhook {
+            sinks.push(Box::new(Webhook(url.clone())));
+        }
+        sinks
+    }
+}
+
+impl Addresses {
+    pub fn new(
+        packages: HashMap<String, String>,
+        components: HashMap<String, String>,
+        resources: HashMap<String, String>,
+        admin_account: String,
+        admin_key: String,
+    ) -> Addresses {
+        // Sorted, so the files only change when the addresses do
+        Addresses {
+            packages: packages.into_iter().collect(),
+            components: components.into_iter().collect(),
+            resources: resources.into_iter().collect(),
+            admin_account,
+            admin_key,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "packages": self.packages,
+            "components": self.components,
+            "resources": self.resources,
+            "admin_account": self.admin_account,
+            "admin_key": self.admin_key
+        })
+    }
+}
+
+impl AddressSink for JsonFile {
+    fn push(&self, addresses: &Addresses) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(&addresses.to_json()).unwrap();
+        write(&self.0, text + "\n")
+    }
+}
+
+impl AddressSink for TypeScriptFile {
+    fn push(&self, addresses: &Addresses) -> Result<(), String> {
+        let mut text = String::from("// Written by rad-rpc on every setup, don't edit\n");
+        for (name, map) in [
+            ("packages", &addresses.packages),
+            ("components", &addresses.components),
+            ("resources", &addresses.resources),
+        ] {
+            text += &format!(
+                "export const {} = {} as const;\n",
+                name,
+                serde_json::to_string_pretty(map).unwrap()
+            );
+        }
+        text += &format!(
+            "export const adminAccount = {};\n",
+            json!(addresses.admin_account)
+        );
+        text += &format!("export const adminKey = {};\n", json!(addresses.admin_key));
+        write(&self.0, text)
+    }
+}
+
+impl AddressSink for EnvFile {
+    fn push(&self, addresses: &Addresses) -> Result<(), String> {
+        let mut text = String::from("# Written by rad-rpc on every setup, don't edit\n");
+        for (kind, map) in [
+            ("PACKAGE", &addresses.packages),
+            ("COMPONENT", &addresses.components),
+            ("RESOURCE", &addresses.resources),
+        ] {
+            for (name, address) in map {
+                text += &format!("{}{}_{}={}\n", self.prefix, kind, env_name(name), address);
+            }
+        }
+        text += &format!("{}ADMIN_ACCOUNT={}\n", self.prefix, addresses.admin_account);
+        text += &format!("{}ADMIN_KEY={}\n", self.prefix, addresses.admin_key);
+        write(&self.path, text)
+    }
+}
+
+impl AddressSink for Webhook {
+    fn push(&self, addresses: &Addresses) -> Result<(), String> {
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(WEBHOOK_CONNECT_TIMEOUT)
+            .timeout(WEBHOOK_TIMEOUT)
+            .build();
+        agent
+            .post(&self.0)
+            .set("Content-Type", "application/json")
+            .send_string(&addresses.to_json().to_string())
+            .map(|_| ())
+            .map_err(|e| format!("POST to {} failed: {}", self.0, e))
+    }
+}
+
+fn write(path: &str, text: String) -> Result<(), String> {
+    std::fs::write(path, text).map_err(|e| format!("Can't write {}: {}", path, e))
+}
+
+/// `machine0` becomes `MACHINE0`, anything that can't be in a variable name becomes `_`.
+fn env_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn addresses() -> Addresses {
+        let mut components = HashMap::new();
+        components.insert("machine0".to_string(), "02aa".to_string());
+        Addresses::new(
+            HashMap::new(),
+            components,
+            HashMap::new(),
+            "02bb".to_string(),
+            "04cc".to_string(),
+        )
+    }
+
+    /// Answers one request with `status` and hands its request line and body over.
+    fn stub_server(status: &'static str) -> (String, mpsc::Receiver<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/addresses", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            )
+            .unwrap();
+            sender
+                .send((request_line, String::from_utf8(body).unwrap()))
+                .unwrap();
+        });
+        (url, receiver)
+    }
+
+    #[test]
+    fn webhook_posts_the_addresses_as_json() {
+        let (url, receiver) = stub_server("200 OK");
+        Webhook(url).push(&addresses()).unwrap();
+
+        let (request_line, body) = receiver.recv().unwrap();
+        assert!(request_line.starts_with("POST /addresses "));
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body, addresses().to_json());
+        assert_eq!(body["components"]["machine0"], "02aa");
+    }
+
+    #[test]
+    fn webhook_reports_an_error_status() {
+        let (url, receiver) = stub_server("500 Internal Server Error");
+        let e = Webhook(url.clone()).push(&addresses()).unwrap_err();
+        assert!(e.starts_with(&format!("POST to {} failed", url)), "{}", e);
+        receiver.recv().unwrap();
+    }
+
+    #[test]
+    fn webhook_reports_an_unreachable_url() {
+        // Bound and dropped right away, nothing listens on the port anymore
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        assert!(Webhook(format!("http://{}/", address))
+            .push(&addresses())
+            .is_err());
+    }
+}
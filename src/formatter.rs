@@ -1,7 +1,7 @@
 use jsonrpc_core::serde_json::json;
+use jsonrpc_core::serde_json::{Map, Value as JsonValue};
 use radix_engine::ledger::*;
-
-use super::LEDGER;
+use radix_engine::model::Actor::SuperUser;
 
 use sbor::any::*;
 use sbor::*;
@@ -197,7 +197,7 @@ pub fn format_custom<L: Ledger>(
         SCRYPTO_TYPE_VID => {
             let vid = Vid::try_from(data).map_err(|_| DecodeError::InvalidCustomData(ty))?;
             vaults.push(vid);
-            Ok(format!("{:?}", vid))
+            Ok(vault_id(vid))
         }
         _ => Err(DecodeError::InvalidType {
             expected: None,
@@ -206,95 +206,156 @@ pub fn format_custom<L: Ledger>(
     }
 }
 
-/*
-fn decode_result(result: Option<SmartValue>) -> std::result::Result<jsonrpc_core::Value, DecodeError> {
-    if let Some(to_decode) = result {
-        let bytes = &to_decode.encoded[..];
-        let decoded = decode_any(bytes);
+/// Formats any data as typed JSON, using ledger state.
+pub fn format_data_json_with_ledger<L: Ledger>(
+    data: &[u8],
+    ledger: &L,
+    vaults: &mut Vec<Vid>,
+) -> Result<JsonValue, DecodeError> {
+    let value = decode_any(data)?;
+    format_value_json(&value, ledger, vaults)
+}
 
-        match decoded {
-            Ok(v) => return format_decoded(v),
-            Err(e) => return decode_error(e),
-        }
+pub fn format_value_json<L: Ledger>(
+    value: &Value,
+    ledger: &L,
+    vaults: &mut Vec<Vid>,
+) -> Result<JsonValue, DecodeError> {
+    match value {
+        // primitive types, 128 bit integers don't fit in a JSON number
+        Value::Unit => Ok(JsonValue::Null),
+        Value::Bool(v) => Ok(json!(v)),
+        Value::I8(v) => Ok(json!(v)),
+        Value::I16(v) => Ok(json!(v)),
+        Value::I32(v) => Ok(json!(v)),
+        Value::I64(v) => Ok(json!(v)),
+        Value::I128(v) => Ok(json!(v.to_string())),
+        Value::U8(v) => Ok(json!(v)),
+        Value::U16(v) => Ok(json!(v)),
+        Value::U32(v) => Ok(json!(v)),
+        Value::U64(v) => Ok(json!(v)),
+        Value::U128(v) => Ok(json!(v.to_string())),
+        Value::String(v) => Ok(json!(v)),
+        // struct & enum
+        Value::Struct(fields) => format_fields_json(fields, ledger, vaults),
+        Value::Enum(index, fields) => Ok(json!({
+            "variant": index,
+            "fields": format_fields_json(fields, ledger, vaults)?
+        })),
+        // rust types
+        Value::Option(v) => match v.borrow() {
+            Some(x) => Ok(json!({ "Some": format_value_json(x, ledger, vaults)? })),
+            None => Ok(json!({ "None": null })),
+        },
+        Value::Box(v) => format_value_json(v.borrow(), ledger, vaults),
+        Value::Array(_, elements) => format_vec_json(elements.iter(), ledger, vaults),
+        Value::Tuple(elements) => format_vec_json(elements.iter(), ledger, vaults),
+        Value::Result(v) => match v.borrow() {
+            Ok(x) => Ok(json!({ "Ok": format_value_json(x, ledger, vaults)? })),
+            Err(x) => Ok(json!({ "Err": format_value_json(x, ledger, vaults)? })),
+        },
+        // collections
+        Value::Vec(_, elements) => format_vec_json(elements.iter(), ledger, vaults),
+        Value::TreeSet(_, elements) => format_vec_json(elements.iter(), ledger, vaults),
+        Value::HashSet(_, elements) => format_vec_json(elements.iter(), ledger, vaults),
+        Value::TreeMap(_, _, elements) => format_map_json(elements.iter(), ledger, vaults),
+        Value::HashMap(_, _, elements) => format_map_json(elements.iter(), ledger, vaults),
+        // custom types
+        Value::Custom(ty, data) => format_custom_json(*ty, data, ledger, vaults),
     }
-
-    Ok(jsonrpc_core::Value::Null)
 }
 
-pub fn decode_all(
-    results: Vec<std::result::Result<Option<SmartValue>, radix_engine::engine::RuntimeError>>,
-    ledger: &InMemoryLedger,
+/// Named fields don't carry their names in SBOR, so both kinds are keyed by index.
+pub fn format_fields_json<L: Ledger>(
+    fields: &Fields,
+    ledger: &L,
     vaults: &mut Vec<Vid>,
-) -> jsonrpc_core::Result<jsonrpc_core::Value> {
-    let vec: Vec<jsonrpc_core::Value> = Vec::new();
-    for result in results {
-        if let Ok(to_decode) = result {
-            match decode_result(to_decode){
-                Ok(v) => vec.push(v),
-                Err(e) => return decode_error(e)
+) -> Result<JsonValue, DecodeError> {
+    let mut map = Map::new();
+    match fields {
+        Fields::Named(values) | Fields::Unnamed(values) => {
+            for (i, x) in values.iter().enumerate() {
+                map.insert(i.to_string(), format_value_json(x, ledger, vaults)?);
             }
-        } else {
-            return runtime_error(result.err().unwrap())
         }
+        Fields::Unit => {}
     }
-    Ok(json!(vec))
+    Ok(JsonValue::Object(map))
 }
 
-
-fn format_decoded(decoded: sbor::any::Value) -> Result<serde_json::Value> {
-    match decoded {
-        sbor::any::Value::Unit => return Ok("()".into()),
-        sbor::any::Value::Bool(value) => return Ok(json!(value)),
-        sbor::any::Value::I8(value) => return Ok(json!(value)),
-        sbor::any::Value::I16(value) => return Ok(json!(value)),
-        sbor::any::Value::I32(value) => return Ok(json!(value)),
-        sbor::any::Value::I64(value) => return Ok(json!(value)),
-        sbor::any::Value::I128(value) => return Ok(json!(value)),
-        sbor::any::Value::U8(value) => return Ok(json!(value)),
-        sbor::any::Value::U16(value) => return Ok(json!(value)),
-        sbor::any::Value::U32(value) => return Ok(json!(value)),
-        sbor::any::Value::U64(value) => return Ok(json!(value)),
-        sbor::any::Value::U128(value) => return Ok(json!(value)),
-        sbor::any::Value::String(value) => return Ok(json!(value)),
-
-        sbor::any::Value::Struct(fields) => {}
-        sbor::any::Value::Enum(u8, Fields) => {} /*
-                                                 sbor::any::Value::Option(Box<Option<sbor::Any::Value>>) => {},
-                                                 sbor::any::Value::Box(Box<Value>) => {},
-                                                 sbor::any::Value::Array(u8, Vec<Value>) => {},
-                                                 sbor::any::Value::Tuple(Vec<Value>) => {},
-                                                 sbor::any::Value::Result(Box<Result<Value, Value>>) => {},
-                                                        sbor::any::Value::Vec(u8, Vec<Value>) => {},
-                                                 sbor::any::Value::TreeSet(u8, Vec<Value>) => {},
-                                                 sbor::any::Value::TreeMap(u8, u8, Vec<(Value, Value)>) => {},
-                                                 sbor::any::Value::HashSet(u8, Vec<Value>) => {},
-                                                 sbor::any::Value::HashMap(u8, u8, Vec<(Value, Value)>) => {},
-
-                                                 sbor::any::Value::Custom(u8, Vec<u8>) => {},
-                                                 */
+pub fn format_vec_json<'a, L: Ledger, I: Iterator<Item = &'a Value>>(
+    itr: I,
+    ledger: &L,
+    vaults: &mut Vec<Vid>,
+) -> Result<JsonValue, DecodeError> {
+    let mut buf = Vec::new();
+    for x in itr {
+        buf.push(format_value_json(x, ledger, vaults)?);
     }
-    Ok(" ".into())
-}
-fn decode_next(data: Option<&[u8]>) -> Result<serde_json::Value> {
-    Ok("()".into())
+    Ok(JsonValue::Array(buf))
 }
 
-fn decode_error(error: DecodeError) -> jsonrpc_core::Result<Value> {
-    eprint!("{:?}", error);
-    Err(jsonrpc_core::Error {
-        code: ErrorCode::ParseError,
-        message: "Error while parsing".into(),
-        data: None,
-    })
+/// Keys aren't necessarily strings, so maps become a list of `[key, value]` pairs.
+pub fn format_map_json<'a, L: Ledger, I: Iterator<Item = &'a (Value, Value)>>(
+    itr: I,
+    ledger: &L,
+    vaults: &mut Vec<Vid>,
+) -> Result<JsonValue, DecodeError> {
+    let mut buf = Vec::new();
+    for x in itr {
+        buf.push(json!([
+            format_value_json(&x.0, ledger, vaults)?,
+            format_value_json(&x.1, ledger, vaults)?
+        ]));
+    }
+    Ok(JsonValue::Array(buf))
 }
 
-fn runtime_error(error: radix_engine::engine::RuntimeError) -> jsonrpc_core::Result<Value> {
-    eprint!("{:?}", error);
-    Err(Error {
-        code: ErrorCode::ParseError,
-        message: "Error while parsing".into(),
-        data: None,
-    })
+pub fn format_custom_json<L: Ledger>(
+    ty: u8,
+    data: &[u8],
+    ledger: &L,
+    vaults: &mut Vec<Vid>,
+) -> Result<JsonValue, DecodeError> {
+    match ty {
+        SCRYPTO_TYPE_MID => {
+            let mid = Mid::try_from(data).map_err(|_| DecodeError::InvalidCustomData(ty))?;
+
+            let mut entries = Vec::new();
+            if let Some(lazy_map) = ledger.get_lazy_map(mid) {
+                for (k, v) in lazy_map.map().iter() {
+                    entries.push(json!([
+                        format_data_json_with_ledger(k, ledger, vaults)?,
+                        format_data_json_with_ledger(v, ledger, vaults)?
+                    ]));
+                }
+            };
+
+            Ok(json!({ "lazy_map": format!("{:?}", mid), "entries": entries }))
+        }
+        SCRYPTO_TYPE_VID => {
+            let vid = Vid::try_from(data).map_err(|_| DecodeError::InvalidCustomData(ty))?;
+            vaults.push(vid);
+
+            // What's in the vault, so a state can be read without looking every vault up
+            let mut vault = json!({ "vault": vault_id(vid) });
+            if let Some(v) = ledger.get_vault(vid) {
+                if let (Ok(resource_def), Ok(amount)) =
+                    (v.resource_address(SuperUser), v.amount(SuperUser))
+                {
+                    vault["resource_def"] = json!(resource_def.to_string());
+                    vault["amount"] = json!(amount.to_string());
+                }
+            }
+            Ok(vault)
+        }
+        // Decimals, addresses and ids are kept as strings so no precision is lost
+        _ => Ok(json!(format_custom(ty, data, ledger, vaults)?)),
+    }
 }
 
-*/
+/// How a vault is named to clients: the hash of the transaction that created it and its index
+/// in that transaction, e.g. `<hash>:2`.
+pub fn vault_id(vid: Vid) -> String {
+    format!("{}:{}", vid.0, vid.1)
+}
@@ -0,0 +1,249 @@
+use super::persist;
+
+use jsonrpc_core::serde_json::{self, json};
+use jsonrpc_core::Value;
+use scrypto::prelude::*;
+
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// The filters of `list_transactions`, each matches the addresses of one role.
+#[derive(Clone, Copy)]
+pub enum Role {
+    Account,
+    Component,
+    Package,
+}
+
+/// The addresses a transaction was called with or created, by the role they played in it.
+#[derive(Clone, Default)]
+pub struct Involved {
+    pub accounts: Vec<Address>,
+    pub components: Vec<Address>,
+    pub packages: Vec<Address>,
+    pub resources: Vec<Address>,
+}
+
+impl Involved {
+    pub fn extend(&mut self, other: Involved) {
+        self.accounts.extend(other.accounts);
+        self.components.extend(other.components);
+        self.packages.extend(other.packages);
+        self.resources.extend(other.resources);
+    }
+
+    /// Adds an entity the transaction created, with `accounts` the new components are accounts.
+    pub fn add_created(&mut self, address: Address, accounts: bool) {
+        if address.is_package() {
+            self.packages.push(address);
+        } else if address.is_component() && accounts {
+            self.accounts.push(address);
+        } else if address.is_component() {
+            self.components.push(address);
+        } else if address.is_resource_def() {
+            self.resources.push(address);
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "accounts": to_strings(&self.accounts),
+            "components": to_strings(&self.components),
+            "packages": to_strings(&self.packages),
+            "resources": to_strings(&self.resources),
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<Involved> {
+        Some(Involved {
+            accounts: from_strings(&value["accounts"])?,
+            components: from_strings(&value["components"])?,
+            packages: from_strings(&value["packages"])?,
+            resources: from_strings(&value["resources"])?,
+        })
+    }
+}
+
+/// A committed transaction as it's kept in the history.
+pub struct TransactionRecord {
+    pub id: u64,
+    // Which RPC method submitted it
+    pub kind: String,
+    pub instructions: Vec<String>,
+    pub signers: Vec<Address>,
+    pub epoch: u64,
+    pub nonce: u64,
+    pub success: bool,
+    pub receipt: Value,
+    pub results: Value,
+    pub involved: Involved,
+}
+
+impl TransactionRecord {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "kind": self.kind,
+            "instructions": self.instructions,
+            "signers": to_strings(&self.signers),
+            "epoch": self.epoch,
+            "nonce": self.nonce,
+            "success": self.success,
+            "receipt": self.receipt,
+            "results": self.results,
+            "involved": self.involved.to_json(),
+        })
+    }
+
+    /// Reads a record back from what `to_json` made of it.
+    fn from_json(value: Value) -> Option<TransactionRecord> {
+        Some(TransactionRecord {
+            id: value["id"].as_u64()?,
+            kind: value["kind"].as_str()?.to_string(),
+            instructions: serde_json::from_value(value["instructions"].clone()).ok()?,
+            signers: from_strings(&value["signers"])?,
+            epoch: value["epoch"].as_u64()?,
+            nonce: value["nonce"].as_u64()?,
+            success: value["success"].as_bool()?,
+            receipt: value["receipt"].clone(),
+            results: value["results"].clone(),
+            involved: Involved::from_json(&value["involved"])?,
+        })
+    }
+
+    /// Whether `address` played `role` in the transaction, the signers count as accounts.
+    pub fn involves(&self, role: Role, address: Address) -> bool {
+        match role {
+            Role::Account => {
+                self.involved.accounts.contains(&address) || self.signers.contains(&address)
+            }
+            Role::Component => self.involved.components.contains(&address),
+            Role::Package => self.involved.packages.contains(&address),
+        }
+    }
+}
+
+/// All committed transactions in order, the id of a transaction is its index. Records are
+/// shared, so a copy for a snapshot is cheap.
+pub struct History {
+    records: Vec<Arc<TransactionRecord>>,
+    // When set every record is appended here, one JSON object per line
+    path: Option<PathBuf>,
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            records: Vec::new(),
+            path: None,
+        }
+    }
+
+    /// Persists the records to `path`, loading the previous ones if the file exists. A last line
+    /// cut off by a crash is dropped.
+    pub fn persist_to(&mut self, path: PathBuf) -> io::Result<()> {
+        let mut unfinished = false;
+        if path.exists() {
+            let file = std::fs::File::open(&path)?;
+            let lines: Vec<String> = BufReader::new(file).lines().collect::<io::Result<_>>()?;
+            let count = lines.len();
+            for (index, line) in lines.into_iter().enumerate() {
+                let record = serde_json::from_str(&line)
+                    .ok()
+                    .and_then(TransactionRecord::from_json);
+                match record {
+                    Some(record) if record.id == self.len() => self.records.push(Arc::new(record)),
+                    _ if index + 1 == count => unfinished = true,
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Corrupted history {}: line {}", path.display(), index + 1),
+                        ))
+                    }
+                }
+            }
+        }
+        self.path = Some(path);
+
+        // Leave no half written line behind for the next record to be appended to
+        if unfinished {
+            eprintln!("Dropping the unfinished last transaction of the history");
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// A copy of the records that is never written to disk.
+    pub fn scratch(&self) -> History {
+        History {
+            records: self.records.clone(),
+            path: None,
+        }
+    }
+
+    /// Replaces all records with the ones of a snapshot, keeps persisting to the same file.
+    pub fn restore(&mut self, snapshot: &History) -> io::Result<()> {
+        self.records = snapshot.records.clone();
+        self.save()
+    }
+
+    /// Stores the record under the next id and returns that id. The record is kept even when it
+    /// can't be written to disk, the transaction was committed after all.
+    pub fn record(&mut self, mut record: TransactionRecord) -> u64 {
+        let id = self.records.len() as u64;
+        record.id = id;
+        let record = Arc::new(record);
+        if let Err(e) = self.append(&record) {
+            eprintln!("Can't persist transaction {}: {}", id, e);
+        }
+        self.records.push(record);
+        id
+    }
+
+    pub fn len(&self) -> u64 {
+        self.records.len() as u64
+    }
+
+    pub fn get(&self, id: u64) -> Option<&TransactionRecord> {
+        self.records.get(id as usize).map(|r| &**r)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &TransactionRecord> {
+        self.records.iter().map(|r| &**r)
+    }
+
+    fn append(&self, record: &TransactionRecord) -> io::Result<()> {
+        if let Some(path) = &self.path {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{}", record.to_json())?;
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if let Some(path) = &self.path {
+            let mut contents = String::new();
+            for record in &self.records {
+                contents.push_str(&record.to_json().to_string());
+                contents.push('\n');
+            }
+            persist::write_atomic(path, contents.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+fn to_strings(addresses: &[Address]) -> Vec<String> {
+    addresses.iter().map(|x| x.to_string()).collect()
+}
+
+fn from_strings(value: &Value) -> Option<Vec<Address>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|v| Address::from_str(v.as_str()?).ok())
+        .collect()
+}
@@ -2,25 +2,35 @@ use std::ptr::addr_of;
 
 use crate::formatter::format_data_with_ledger;
 
+use super::arguments;
 use super::formatter;
+use super::history::{Involved, Role, TransactionRecord};
+use super::ledger::RpcLedger;
+use super::manifest;
+use super::manifest::ManifestInstruction;
+use super::manifest_parser;
 use super::scrypto_helpers;
+use super::snapshot::Snapshot;
+use super::subscriptions::Topic;
+use super::trace;
 
-use super::CONFIG;
-use super::LEDGER;
+use super::state::{ServerState, State};
 
 use ::jsonrpc_core::serde_json::json;
 use jsonrpc_core::serde::Deserialize;
-use jsonrpc_core::serde_json::Number;
 use jsonrpc_core::*;
-use jsonrpc_http_server::*;
-
-use parking_lot::RwLockWriteGuard;
+use jsonrpc_pubsub::{PubSubHandler, Session, Subscriber, SubscriptionId};
 
 use radix_engine::ledger::*;
 use radix_engine::model::Actor::SuperUser;
 use radix_engine::transaction::*;
 use scrypto::prelude::*;
 
+use std::cell::Cell;
+use std::io::BufRead;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
 // Structs to use as Params for our functions
 #[derive(Deserialize)]
 struct CallFunction {
@@ -30,6 +40,12 @@ struct CallFunction {
     args: Vec<String>,
     account_address: String,
     key: String,
+    // Run against a throwaway copy of the ledger without committing anything
+    #[serde(default)]
+    preview: bool,
+    // Capture the engine trace, falls back to the server default when not given
+    #[serde(default)]
+    trace: Option<bool>,
 }
 
 #[derive(Deserialize)]
@@ -39,290 +55,1488 @@ struct CallMethod {
     args: Vec<String>,
     account_address: String,
     key: String,
+    // "json" (default) for typed JSON results, "display" for the old string form
+    #[serde(default)]
+    format: Option<String>,
+    #[serde(default)]
+    preview: bool,
+    #[serde(default)]
+    trace: Option<bool>,
+    #[serde(default)]
+    receipt: bool,
+}
+
+#[derive(Deserialize)]
+struct SubmitTransaction {
+    instructions: Vec<ManifestInstruction>,
+    signers: Vec<String>,
+    #[serde(default)]
+    format: Option<String>,
+    #[serde(default)]
+    preview: bool,
+    #[serde(default)]
+    trace: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct SubmitManifest {
+    manifest: String,
+    signers: Vec<String>,
+    // Values for the $names used in the manifest
+    #[serde(default)]
+    variables: HashMap<String, String>,
+    #[serde(default)]
+    format: Option<String>,
+    #[serde(default)]
+    preview: bool,
+    #[serde(default)]
+    trace: Option<bool>,
 }
 
 #[derive(Deserialize)]
 struct GetBalance {
     address: String,
+    // Add up the vaults that hold the same resource
+    #[serde(default)]
+    aggregate: bool,
+}
+
+#[derive(Deserialize)]
+struct GetComponentState {
+    address: String,
+}
+
+#[derive(Deserialize)]
+struct GetResourceDef {
+    address: String,
+}
+
+#[derive(Deserialize)]
+struct GetPackage {
+    address: String,
+}
+
+#[derive(Deserialize)]
+struct GetBlueprintAbi {
+    package: String,
+    blueprint: String,
+}
+
+#[derive(Deserialize)]
+struct SnapshotId {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct GetTransaction {
+    id: u64,
+}
+
+#[derive(Deserialize, Default)]
+struct ListTransactions {
+    #[serde(default)]
+    offset: usize,
+    #[serde(default)]
+    limit: Option<usize>,
+    #[serde(default)]
+    account: Option<String>,
+    #[serde(default)]
+    component: Option<String>,
+    #[serde(default)]
+    package: Option<String>,
+    #[serde(default)]
+    success: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct ResolveName {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct RegisterName {
+    name: String,
+    address: String,
+}
+
+#[derive(Deserialize, Default)]
+struct SandboxCreate {
+    // Start from the ledger as it was after the setup instead of a freshly bootstrapped one
+    #[serde(default)]
+    from_setup: bool,
+}
+
+#[derive(Deserialize)]
+struct SandboxId {
+    id: u64,
+}
+
+/// How to run a transaction and what to remember about it in the history
+struct Execution {
+    // Name of the RPC method that submitted it
+    kind: &'static str,
+    preview: bool,
+    trace: Option<bool>,
+    signers: Vec<Address>,
+    // Addresses the transaction is called with, used to filter the history
+    involved: Involved,
+}
+
+/// Serves the same methods over stdin and stdout, one request per line, until stdin closes.
+pub fn stdio_thread(state: &State) {
+    let mut io = IoHandler::new();
+    add_methods(&mut io, state);
+
+    let stdin = std::io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // Notifications have no response
+        if let Some(response) = io.handle_request_sync(&line) {
+            trace::write_response(&response);
+        }
+    }
 }
 
-pub fn rpc_thread() {
-    // Create new IoHandler
-    let mut io = IoHandler::default();
+/// Adds all methods that should be callable through the JSON-RPC server, on every transport.
+pub fn add_methods<M: Metadata>(io: &mut MetaIoHandler<M>, state: &State) {
+    add_method(io, state, "new_account", |state, _params| {
+        new_account(state)
+    });
 
-    // Add all methods that should be callable through the JSON-RPC server
-    io.add_method("new_account", |_params| async move { new_account() });
+    add_method(io, state, "call_function", |state, params| {
+        let parsed = params.parse().ok();
+        match parsed {
+            Some(v) => call_function(state, v),
+            None => return parse_err(),
+        }
+    });
 
-    io.add_method("call_function", |params: Params| async move {
+    add_method(io, state, "call_method", |state, params| {
         let parsed = params.parse().ok();
         match parsed {
-            Some(v) => call_function(v),
+            Some(v) => call_method(state, v),
             None => return parse_err(),
         }
     });
 
-    io.add_method("call_method", |params: Params| async move {
+    add_method(io, state, "submit_transaction", |state, params| {
         let parsed = params.parse().ok();
         match parsed {
-            Some(v) => call_method(v),
+            Some(v) => submit_transaction(state, v),
             None => return parse_err(),
         }
     });
 
-    io.add_method("get_balance", |params: Params| async move {
+    add_method(io, state, "submit_manifest", |state, params| {
         let parsed = params.parse().ok();
         match parsed {
-            Some(v) => get_balance(v),
+            Some(v) => submit_manifest(state, v),
             None => return parse_err(),
         }
     });
 
-    // Start the server
-    let server = ServerBuilder::new(io)
-        .threads(4)
-        .cors(DomainsValidation::AllowOnly(vec![
-            AccessControlAllowOrigin::Any,
-        ]))
-        .start_http(&"127.0.0.1:3030".parse().unwrap())
-        .expect("Unable to start RPC server");
-    server.wait();
-}
-
-fn new_account() -> jsonrpc_core::Result<jsonrpc_core::Value> {
-    //Instantiate with dummy values to move into closure
-    let mut key: Address =
-        Address::from_str("02b8dd9f4232ce3c00dcb3496956fb57096d5d50763b989ca56f3b").unwrap();
-    let mut account: Address =
-        Address::from_str("02b9f7c0c44a6e2162403cea3fa44500dff50eb18fd4ff5a9dd079").unwrap();
-
-    // Can't use RwLockWriteGuard::unlocked's FnOnce because we need to hold the lock on config for
-    // as long as we're using the ledger. Otherwise the nonce might go out of sync. Make sure to
-    // ALWAYS call .write() and keep the lock open for as long as you use the ledger. It is a bit
-    // uglier then FnOnce but it is correct.
-    let write_lock_conf = CONFIG.write();
-    let map_config = RwLockWriteGuard::map(write_lock_conf, |config| {
-        let (epoch, nonce) = config.load_nonce();
-
-        // Acquire the ledger from the inside of the RwLock, always return it at the end
-        let write_lock_ledger = LEDGER.write();
-        let map_ledger = RwLockWriteGuard::map(write_lock_ledger, |ledger| {
-            //Do transaction
-            let mut executor = TransactionExecutor::new(ledger, epoch, nonce);
-            key = executor.new_public_key();
-            account = executor.new_account(key);
-
-            //Store the nonce and return the ledger and config
-            config.store_nonce(&executor);
-            ledger
-        });
-        config
+    add_method(io, state, "get_balance", |state, params| {
+        let parsed = params.parse().ok();
+        match parsed {
+            Some(v) => get_balance(state, v),
+            None => return parse_err(),
+        }
+    });
+
+    add_method(io, state, "get_component_state", |state, params| {
+        let parsed = params.parse().ok();
+        match parsed {
+            Some(v) => get_component_state(state, v),
+            None => return parse_err(),
+        }
+    });
+
+    add_method(io, state, "get_resource_def", |state, params| {
+        let parsed = params.parse().ok();
+        match parsed {
+            Some(v) => get_resource_def(state, v),
+            None => return parse_err(),
+        }
+    });
+
+    add_method(io, state, "get_package", |state, params| {
+        let parsed = params.parse().ok();
+        match parsed {
+            Some(v) => get_package(state, v),
+            None => return parse_err(),
+        }
+    });
+
+    add_method(io, state, "get_blueprint_abi", |state, params| {
+        let parsed = params.parse().ok();
+        match parsed {
+            Some(v) => get_blueprint_abi(state, v),
+            None => return parse_err(),
+        }
+    });
+
+    add_method(io, state, "snapshot_create", |state, _params| {
+        snapshot_create(state)
+    });
+
+    add_method(io, state, "snapshot_restore", |state, params| {
+        let parsed = params.parse().ok();
+        match parsed {
+            Some(v) => snapshot_restore(state, v),
+            None => return parse_err(),
+        }
+    });
+
+    add_method(io, state, "snapshot_list", |state, _params| {
+        snapshot_list(state)
+    });
+
+    add_method(io, state, "snapshot_delete", |state, params| {
+        let parsed = params.parse().ok();
+        match parsed {
+            Some(v) => snapshot_delete(state, v),
+            None => return parse_err(),
+        }
+    });
+
+    add_method(io, state, "get_transaction", |state, params| {
+        let parsed = params.parse().ok();
+        match parsed {
+            Some(v) => get_transaction(state, v),
+            None => return parse_err(),
+        }
+    });
+
+    add_method(io, state, "list_transactions", |state, params| {
+        // All filters are optional, so allow calling it without any params
+        let parsed = match params {
+            Params::None => Some(ListTransactions::default()),
+            params => params.parse().ok(),
+        };
+        match parsed {
+            Some(v) => list_transactions(state, v),
+            None => return parse_err(),
+        }
+    });
+
+    add_method(io, state, "get_named_addresses", |state, _params| {
+        get_named_addresses(state)
+    });
+
+    add_method(io, state, "resolve_name", |state, params| {
+        let parsed = params.parse().ok();
+        match parsed {
+            Some(v) => resolve_name(state, v),
+            None => return parse_err(),
+        }
+    });
+
+    add_method(io, state, "register_name", |state, params| {
+        let parsed = params.parse().ok();
+        match parsed {
+            Some(v) => register_name(state, v),
+            None => return parse_err(),
+        }
+    });
+
+    add_sandbox_method(io, state, "sandbox_create", |state, params| {
+        let parsed = match params {
+            Params::None => Some(SandboxCreate::default()),
+            params => params.parse().ok(),
+        };
+        match parsed {
+            Some(v) => sandbox_create(state, v),
+            None => return parse_err(),
+        }
+    });
+
+    add_sandbox_method(io, state, "sandbox_delete", |state, params| {
+        let parsed = params.parse().ok();
+        match parsed {
+            Some(v) => sandbox_delete(state, v),
+            None => return parse_err(),
+        }
+    });
+
+    add_sandbox_method(io, state, "sandbox_list", |state, _params| {
+        sandbox_list(state)
+    });
+}
+
+/// Registers a method that runs against the state of this server, or against the sandbox picked
+/// by the `sandbox` param.
+fn add_method<M, F>(io: &mut MetaIoHandler<M>, state: &State, name: &str, method: F)
+where
+    M: Metadata,
+    F: Fn(&ServerState, Params) -> jsonrpc_core::Result<Value> + Send + Sync + 'static,
+{
+    let state = state.clone();
+    io.add_method(name, move |params: Params| {
+        let result = route(&state, params).and_then(|(state, params)| method(&state, params));
+        async move { result }
     });
-    Ok(json!({"key": key.to_string(), "account": account.to_string()}))
 }
 
-fn call_function(params: CallFunction) -> jsonrpc_core::Result<jsonrpc_core::Value> {
+/// Registers a method that manages the sandboxes, it always runs against the main server.
+fn add_sandbox_method<M, F>(io: &mut MetaIoHandler<M>, state: &State, name: &str, method: F)
+where
+    M: Metadata,
+    F: Fn(&ServerState, Params) -> jsonrpc_core::Result<Value> + Send + Sync + 'static,
+{
+    let state = state.clone();
+    io.add_method(name, move |params: Params| {
+        let result = method(&state, params);
+        async move { result }
+    });
+}
+
+/// Picks the state a call runs against and returns the params without the sandbox. Named params
+/// pick one with a `sandbox` entry, positional params with a trailing `{"sandbox": id}`.
+fn route(state: &State, params: Params) -> jsonrpc_core::Result<(State, Params)> {
+    match params {
+        Params::Map(mut map) => {
+            let sandbox = match map.remove("sandbox") {
+                Some(sandbox) => sandbox,
+                None => return Ok((state.clone(), Params::Map(map))),
+            };
+            let sandbox = find_sandbox(state, &sandbox)?;
+            // Methods without params expect none at all
+            if map.is_empty() {
+                Ok((sandbox, Params::None))
+            } else {
+                Ok((sandbox, Params::Map(map)))
+            }
+        }
+        Params::Array(mut values) => {
+            let sandbox = match values.last() {
+                Some(Value::Object(last)) if last.len() == 1 => match last.get("sandbox") {
+                    Some(sandbox) => find_sandbox(state, sandbox)?,
+                    None => return Ok((state.clone(), Params::Array(values))),
+                },
+                _ => return Ok((state.clone(), Params::Array(values))),
+            };
+            values.pop();
+            if values.is_empty() {
+                Ok((sandbox, Params::None))
+            } else {
+                Ok((sandbox, Params::Array(values)))
+            }
+        }
+        params => Ok((state.clone(), params)),
+    }
+}
+
+fn find_sandbox(state: &State, id: &Value) -> jsonrpc_core::Result<State> {
+    let sandbox = match id.as_u64() {
+        Some(id) => state.sandboxes.read().get(id),
+        None => None,
+    };
+    sandbox.ok_or_else(|| Error {
+        code: ErrorCode::InvalidParams,
+        message: "Unknown sandbox id".to_string(),
+        data: None,
+    })
+}
+
+/// Subscriptions only work over transports with a session, the client gets a notification with
+/// the same name as the topic.
+pub fn add_subscriptions(io: &mut PubSubHandler<Arc<Session>>, state: &State) {
+    let subscribe_state = state.clone();
+    io.add_subscription(
+        "transactions",
+        (
+            "subscribe_transactions",
+            move |params: Params, _meta: Arc<Session>, subscriber: Subscriber| match route(
+                &subscribe_state,
+                params,
+            ) {
+                Ok((state, _)) => state
+                    .subscriptions
+                    .write()
+                    .subscribe(Topic::Transactions, subscriber),
+                Err(e) => {
+                    let _ = subscriber.reject(e);
+                }
+            },
+        ),
+        ("unsubscribe_transactions", unsubscribe(state)),
+    );
+
+    let subscribe_state = state.clone();
+    io.add_subscription(
+        "logs",
+        (
+            "subscribe_logs",
+            move |params: Params, _meta: Arc<Session>, subscriber: Subscriber| match route(
+                &subscribe_state,
+                params,
+            ) {
+                Ok((state, _)) => state
+                    .subscriptions
+                    .write()
+                    .subscribe(Topic::Logs, subscriber),
+                Err(e) => {
+                    let _ = subscriber.reject(e);
+                }
+            },
+        ),
+        ("unsubscribe_logs", unsubscribe(state)),
+    );
+
+    let subscribe_state = state.clone();
+    io.add_subscription(
+        "component",
+        (
+            "subscribe_component",
+            move |params: Params, _meta: Arc<Session>, subscriber: Subscriber| {
+                let (state, params) = match route(&subscribe_state, params) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        let _ = subscriber.reject(e);
+                        return;
+                    }
+                };
+                let parsed: Option<GetBalance> = params.parse().ok();
+                let address = parsed.and_then(|v| {
+                    let names = state.registry.read();
+                    names.parse_address(&v.address, "Component address").ok()
+                });
+                match address {
+                    Some(address) if address.is_component() => state
+                        .subscriptions
+                        .write()
+                        .subscribe(Topic::Component(address), subscriber),
+                    _ => {
+                        let _ = subscriber.reject(Error {
+                            code: ErrorCode::InvalidParams,
+                            message: "Component address wrong format".to_string(),
+                            data: None,
+                        });
+                    }
+                }
+            },
+        ),
+        ("unsubscribe_component", unsubscribe(state)),
+    );
+}
+
+fn unsubscribe(
+    state: &State,
+) -> impl Fn(
+    SubscriptionId,
+    Option<Arc<Session>>,
+) -> jsonrpc_core::futures::future::Ready<jsonrpc_core::Result<Value>>
+       + Send
+       + Sync
+       + 'static {
+    let state = state.clone();
+    move |id, _meta| {
+        // Subscription ids are unique across sandboxes, so just try all of them
+        let mut removed = state.subscriptions.write().unsubscribe(&id);
+        if !removed {
+            let sandboxes = state.sandboxes.read();
+            for (_, sandbox) in sandboxes.iter() {
+                if sandbox.subscriptions.write().unsubscribe(&id) {
+                    removed = true;
+                    break;
+                }
+            }
+        }
+        jsonrpc_core::futures::future::ready(Ok(json!(removed)))
+    }
+}
+
+fn new_account(state: &ServerState) -> jsonrpc_core::Result<jsonrpc_core::Value> {
+    // The key is only known once the executor handed it out
+    let key: Cell<Option<Address>> = Cell::new(None);
+
+    let execution = Execution {
+        kind: "new_account",
+        preview: false,
+        trace: None,
+        signers: Vec::new(),
+        involved: Involved::default(),
+    };
+
+    execute(
+        state,
+        execution,
+        |executor| {
+            let new_key = executor.new_public_key();
+            key.set(Some(new_key));
+
+            // The same transaction the executor's new_account runs, so it gets a receipt and
+            // ends up in the history like any other
+            let free_xrd = Decimal::from(1_000_000);
+            TransactionBuilder::new(&*executor)
+                .call_method(
+                    SYSTEM_COMPONENT,
+                    "free_xrd",
+                    vec![free_xrd.to_string()],
+                    None,
+                )
+                .new_account_with_resource(new_key, free_xrd, RADIX_TOKEN)
+                .build(Vec::new())
+                .map_err(|_| transaction_error())
+        },
+        |receipt, _ledger| {
+            let (_, components, _) = scrypto_helpers::get_call_results(&receipt);
+            match (key.get(), components.first()) {
+                (Some(key), Some(account)) => {
+                    Ok(json!({"key": key.to_string(), "account": account.to_string()}))
+                }
+                _ => result_err("Account wasn't created"),
+            }
+        },
+    )
+}
+
+fn call_function(
+    state: &ServerState,
+    params: CallFunction,
+) -> jsonrpc_core::Result<jsonrpc_core::Value> {
     // Parse all values
     let package: Address;
-    match Address::from_str(&params.address) {
+    match parse_address(state, &params.address, "Package address") {
         Ok(v) => package = v,
-        Err(e) => return invalid_params_err("Package address wrong format"),
+        Err(e) => return Err(e),
     }
 
     let account: Address;
-    match Address::from_str(&params.account_address) {
+    match parse_address(state, &params.account_address, "Account") {
         Ok(v) => account = v,
-        Err(e) => return invalid_params_err("Account wrong format"),
+        Err(e) => return Err(e),
     }
 
     let signer: Address;
-    match Address::from_str(&params.key) {
+    match parse_address(state, &params.key, "Signer key") {
         Ok(v) => signer = v,
-        Err(e) => return invalid_params_err("Signer key wrong format"),
+        Err(e) => return Err(e),
     }
     let signers = vec![signer];
 
-    // Declare receipt here so we can return the lock faster
-    let mut receipt: Option<Receipt> = None;
-
-    let write_lock_conf = CONFIG.write();
-    let map_config = RwLockWriteGuard::map(write_lock_conf, |config| {
-        let (epoch, nonce) = config.load_nonce();
-
-        // Acquire the ledger from the inside of the RwLock, always return it at the end
-        let write_lock_ledger = LEDGER.write();
-        let map_ledger = RwLockWriteGuard::map(write_lock_ledger, |ledger| {
-            //Do transaction
-            let mut executor = TransactionExecutor::new(ledger, epoch, nonce);
-            let transaction = TransactionBuilder::new(&executor)
-                .call_function(
-                    package,
-                    &params.name,
-                    &params.function,
-                    params.args,
-                    Some(account),
-                )
-                .drop_all_bucket_refs()
-                .deposit_all_buckets(account)
-                .build(signers);
-            if let Ok(transaction) = transaction {
-                receipt = Some(executor.run(transaction, false).unwrap());
-            }
-            //Store the nonce and return the ledger and config
-            config.store_nonce(&executor);
-            ledger
-        });
-        config
-    });
+    let args = match state.registry.read().resolve_args(params.args.clone()) {
+        Ok(v) => v,
+        Err(e) => return invalid_params_err(&e),
+    };
 
-    match receipt {
-        Some(receipt) => {
-            let (resources, components, _) = scrypto_helpers::get_call_results(receipt);
-            let res: Vec<String> = resources.iter().map(|x| x.to_string()).collect();
-            let com: Vec<String> = components.iter().map(|x| x.to_string()).collect();
+    with_abi_provider(state, package, |abi_provider| {
+        arguments::check_function_args(
+            abi_provider,
+            package,
+            &params.name,
+            &params.function,
+            &args,
+            &params.args,
+        )
+    })
+    .map_err(argument_error)?;
 
-            Ok(json!({ "resources": res, "components": com }))
-        }
-        None => transaction_err(),
-    }
+    let preview = params.preview;
+
+    let execution = Execution {
+        kind: "call_function",
+        preview: params.preview,
+        trace: params.trace,
+        signers: signers.clone(),
+        involved: Involved {
+            accounts: vec![account],
+            packages: vec![package],
+            ..Involved::default()
+        },
+    };
+
+    execute(
+        state,
+        execution,
+        |executor| {
+            TransactionBuilder::new(&*executor)
+                .call_function(package, &params.name, &params.function, args, Some(account))
+                .drop_all_bucket_refs()
+                .deposit_all_buckets(account)
+                .build(signers)
+                .map_err(|_| transaction_error())
+        },
+        |receipt, ledger| {
+            // The original response is an object, so the receipt is just one more field
+            let mut result = original_response(&receipt, ledger, preview, false, |_| {
+                Ok(call_function_result(&receipt))
+            })?;
+            result["receipt"] = scrypto_helpers::receipt_to_json(&receipt, ledger, false);
+            Ok(result)
+        },
+    )
 }
 
-fn call_method(params: CallMethod) -> jsonrpc_core::Result<Value> {
+fn call_method(state: &ServerState, params: CallMethod) -> jsonrpc_core::Result<Value> {
     // Parse all values
     let component: Address;
-    match Address::from_str(&params.address) {
+    match parse_address(state, &params.address, "Component address") {
         Ok(v) => component = v,
-        Err(e) => return invalid_params_err("Package address wrong format"),
+        Err(e) => return Err(e),
     }
 
     // parse values
     let account: Address;
-    match Address::from_str(&params.account_address) {
+    match parse_address(state, &params.account_address, "Account") {
         Ok(v) => account = v,
-        Err(e) => return invalid_params_err("Account wrong format"),
+        Err(e) => return Err(e),
     }
 
     let signer: Address;
-    match Address::from_str(&params.key) {
+    match parse_address(state, &params.key, "Signer key") {
         Ok(v) => signer = v,
-        Err(e) => return invalid_params_err("Signer key wrong format"),
+        Err(e) => return Err(e),
     }
     let signers = vec![signer];
 
-    // Declare receipt here so we can return the lock faster
-    let mut receipt: Option<Receipt> = None;
+    let display = match parse_format(&params.format) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+
+    let args = match state.registry.read().resolve_args(params.args.clone()) {
+        Ok(v) => v,
+        Err(e) => return invalid_params_err(&e),
+    };
 
-    let write_lock_conf = CONFIG.write();
-    let map_config = RwLockWriteGuard::map(write_lock_conf, |config| {
-        let (epoch, nonce) = config.load_nonce();
+    with_abi_provider(state, component, |abi_provider| {
+        arguments::check_method_args(abi_provider, component, &params.method, &args, &params.args)
+    })
+    .map_err(argument_error)?;
 
-        // Acquire the ledger from the inside of the RwLock, always return it at the end
-        let write_lock_ledger = LEDGER.write();
-        let map_ledger = RwLockWriteGuard::map(write_lock_ledger, |ledger| {
-            //Do transaction
-            let mut executor = TransactionExecutor::new(ledger, epoch, nonce);
-            let transaction = TransactionBuilder::new(&executor)
-                .call_method(component, &params.method, params.args, Some(account))
+    // A trace can only be added to the receipt form, so settle on the server default up front
+    let trace = params
+        .trace
+        .unwrap_or_else(|| state.config.read().trace.load(Ordering::SeqCst));
+    let full_receipt = params.receipt || trace;
+    let preview = params.preview;
+
+    let execution = Execution {
+        kind: "call_method",
+        preview: params.preview,
+        trace: Some(trace),
+        signers: signers.clone(),
+        involved: Involved {
+            accounts: vec![account],
+            components: vec![component],
+            ..Involved::default()
+        },
+    };
+
+    execute(
+        state,
+        execution,
+        |executor| {
+            TransactionBuilder::new(&*executor)
+                .call_method(component, &params.method, args, Some(account))
                 .drop_all_bucket_refs()
                 .deposit_all_buckets(account)
-                .build(signers);
-            if let Ok(transaction) = transaction {
-                if let Ok(r) = executor.run(transaction, false) {
-                    receipt = Some(r);
-                }
+                .build(signers)
+                .map_err(|_| transaction_error())
+        },
+        |receipt, ledger| {
+            if !full_receipt {
+                return original_response(&receipt, ledger, preview, display, |ledger| {
+                    decode_results(&receipt, ledger, display)
+                });
             }
-            //Store the nonce and return the ledger and config
-            config.store_nonce(&executor);
-            ledger
+
+            let results = decode_results(&receipt, ledger, display)?;
+            let receipt = scrypto_helpers::receipt_to_json(&receipt, ledger, display);
+
+            Ok(json!({ "results": results, "receipt": receipt }))
+        },
+    )
+}
+
+fn call_function_result(receipt: &Receipt) -> Value {
+    let (resources, components, _) = scrypto_helpers::get_call_results(receipt);
+    let res: Vec<String> = resources.iter().map(|x| x.to_string()).collect();
+    let com: Vec<String> = components.iter().map(|x| x.to_string()).collect();
+    json!({ "resources": res, "components": com })
+}
+
+/// The responses from before receipts were returned, for callers that didn't ask for one. A
+/// preview shows its results, the entities it would create and its logs.
+fn original_response<F>(
+    receipt: &Receipt,
+    ledger: &RpcLedger,
+    preview: bool,
+    display: bool,
+    committed: F,
+) -> jsonrpc_core::Result<Value>
+where
+    F: FnOnce(&RpcLedger) -> jsonrpc_core::Result<Value>,
+{
+    if !preview {
+        return committed(ledger);
+    }
+
+    let results = decode_results(receipt, ledger, display)?;
+    let new_entities: Vec<String> = receipt.new_entities.iter().map(|x| x.to_string()).collect();
+    let logs: Vec<String> = receipt
+        .logs
+        .iter()
+        .map(|(level, message)| format!("[{:?}] {}", level, message))
+        .collect();
+
+    Ok(json!({ "results": results, "new_entities": new_entities, "logs": logs }))
+}
+
+fn submit_transaction(
+    state: &ServerState,
+    params: SubmitTransaction,
+) -> jsonrpc_core::Result<Value> {
+    let steps = match manifest::resolve_all(params.instructions, &state.registry.read()) {
+        Ok(v) => v,
+        Err(e) => return invalid_params_err(&e),
+    };
+
+    run_steps(
+        state,
+        "submit_transaction",
+        steps,
+        &params.signers,
+        &params.format,
+        params.preview,
+        params.trace,
+    )
+}
+
+fn submit_manifest(state: &ServerState, params: SubmitManifest) -> jsonrpc_core::Result<Value> {
+    let parsed = {
+        let names = state.registry.read();
+        manifest_parser::parse_manifest(&params.manifest, &params.variables, &names)
+    };
+    let steps = match parsed {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(Error {
+                code: ErrorCode::InvalidParams,
+                message: e.to_string(),
+                data: Some(json!({ "line": e.line, "column": e.column })),
+            })
+        }
+    };
+
+    run_steps(
+        state,
+        "submit_manifest",
+        steps,
+        &params.signers,
+        &params.format,
+        params.preview,
+        params.trace,
+    )
+}
+
+/// Runs all steps of a manifest as one transaction.
+fn run_steps(
+    state: &ServerState,
+    kind: &'static str,
+    steps: Vec<manifest::Step>,
+    keys: &[String],
+    format: &Option<String>,
+    preview: bool,
+    trace: Option<bool>,
+) -> jsonrpc_core::Result<Value> {
+    let mut signers: Vec<Address> = Vec::new();
+    for key in keys {
+        match parse_address(state, key, "Signer key") {
+            Ok(v) => signers.push(v),
+            Err(e) => return Err(e),
+        }
+    }
+
+    let display = match parse_format(format) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+
+    let execution = Execution {
+        kind,
+        preview,
+        trace,
+        signers: signers.clone(),
+        involved: steps
+            .iter()
+            .fold(Involved::default(), |mut involved, step| {
+                involved.extend(step.involved());
+                involved
+            }),
+    };
+
+    execute(
+        state,
+        execution,
+        |executor| {
+            manifest::build_transaction(&*executor, steps, signers).map_err(|_| transaction_error())
+        },
+        |receipt, ledger| {
+            let results = decode_results(&receipt, ledger, display)?;
+            let receipt = scrypto_helpers::receipt_to_json(&receipt, ledger, display);
+
+            Ok(json!({ "results": results, "receipt": receipt }))
+        },
+    )
+}
+
+/// Parses an address or looks up a registered `@name`, `what` names the value in the error.
+fn parse_address(state: &ServerState, value: &str, what: &str) -> jsonrpc_core::Result<Address> {
+    state
+        .registry
+        .read()
+        .parse_address(value, what)
+        .map_err(|message| Error {
+            code: ErrorCode::InvalidParams,
+            message,
+            data: None,
+        })
+}
+
+/// Returns whether results should use the old display strings instead of typed JSON.
+fn parse_format(format: &Option<String>) -> std::result::Result<bool, jsonrpc_core::Result<Value>> {
+    match format.as_deref() {
+        None | Some("json") => Ok(false),
+        Some("display") => Ok(true),
+        Some(_) => Err(invalid_params_err("Format must be either json or display")),
+    }
+}
+
+/// Builds and runs a transaction, then hands the receipt of a successful transaction to `handle`
+/// together with the ledger it ran on. A failed transaction returns its receipt as error data.
+/// When previewing, the transaction runs on a throwaway copy of the ledger and neither the ledger
+/// nor the nonce are touched. Otherwise the transaction is recorded in the history and its id is
+/// added to the response, just like the trace lines of a traced transaction.
+fn execute<B, H>(
+    state: &ServerState,
+    execution: Execution,
+    build: B,
+    handle: H,
+) -> jsonrpc_core::Result<Value>
+where
+    B: FnOnce(&mut TransactionExecutor<RpcLedger>) -> jsonrpc_core::Result<Transaction>,
+    H: FnOnce(Receipt, &RpcLedger) -> jsonrpc_core::Result<Value>,
+{
+    if execution.preview {
+        let (epoch, nonce, trace, mut scratch) = {
+            let config = state.config.read();
+            let (epoch, nonce) = config.load_nonce();
+            let trace = execution
+                .trace
+                .unwrap_or_else(|| config.trace.load(Ordering::SeqCst));
+            (epoch, nonce, trace, state.ledger.read().scratch())
+        };
+
+        let mut executor = TransactionExecutor::new(&mut scratch, epoch, nonce);
+        let transaction = build(&mut executor)?;
+        let (receipt, lines) = scrypto_helpers::run_transaction(&mut executor, transaction, trace);
+        let result = handle_receipt(receipt, &scratch, handle);
+        return with_field(result, "trace", lines.map(|l| json!(l)));
+    }
+
+    // We need to hold the lock on config for as long as we're using the ledger. Otherwise the
+    // nonce might go out of sync. ALWAYS lock the config before the ledger.
+    let mut config = state.config.write();
+    let mut ledger = state.ledger.write();
+    let (epoch, nonce) = config.load_nonce();
+    let trace = execution
+        .trace
+        .unwrap_or_else(|| config.trace.load(Ordering::SeqCst));
+
+    // Remember how watched components look, so subscribers only hear about actual changes
+    let watched = state.subscriptions.read().components();
+    let before: Vec<Option<Value>> = watched
+        .iter()
+        .map(|address| component_snapshot(&ledger, *address))
+        .collect();
+
+    // Whatever a transaction that panicked halfway left behind must not be committed
+    ledger.discard();
+    let mut executor = TransactionExecutor::new(&mut *ledger, epoch, nonce);
+    let transaction = build(&mut executor)?;
+    let (receipt, lines) = scrypto_helpers::run_transaction(&mut executor, transaction, trace);
+    let next_nonce = executor.nonce();
+
+    // The changes and the nonce are stored together, if that fails neither happened
+    if let Err(e) = ledger.commit(epoch, next_nonce) {
+        ledger.discard();
+        return Err(persist_error(e));
+    }
+    if let Err(e) = config.store_nonce(next_nonce) {
+        eprintln!("Can't persist the nonce: {}", e);
+    }
+
+    let id = receipt
+        .as_ref()
+        .map(|r| record_transaction(state, &execution, r, &ledger, epoch, nonce));
+
+    if let (Some(id), Some(receipt)) = (id, &receipt) {
+        publish_transaction(state, id, receipt);
+        for (address, before) in watched.iter().zip(before) {
+            let after = component_snapshot(&ledger, *address);
+            if after != before {
+                let update = json!({
+                    "component": address.to_string(),
+                    "transaction_id": id,
+                    "component_state": after
+                });
+                state
+                    .subscriptions
+                    .write()
+                    .notify(&Topic::Component(*address), update);
+            }
+        }
+    }
+
+    let result = handle_receipt(receipt, &ledger, handle);
+    let result = with_field(result, "transaction_id", id.map(|id| json!(id)));
+    with_field(result, "trace", lines.map(|l| json!(l)))
+}
+
+fn handle_receipt<H>(
+    receipt: Option<Receipt>,
+    ledger: &RpcLedger,
+    handle: H,
+) -> jsonrpc_core::Result<Value>
+where
+    H: FnOnce(Receipt, &RpcLedger) -> jsonrpc_core::Result<Value>,
+{
+    match receipt {
+        Some(receipt) if !receipt.success => failed_err(&receipt, ledger),
+        Some(receipt) => handle(receipt, ledger),
+        None => transaction_err(),
+    }
+}
+
+/// Adds a field to the response, or to the error data if the transaction failed.
+fn with_field(
+    result: jsonrpc_core::Result<Value>,
+    key: &str,
+    value: Option<Value>,
+) -> jsonrpc_core::Result<Value> {
+    let value = match value {
+        Some(value) => value,
+        None => return result,
+    };
+
+    match result {
+        Ok(mut v) => {
+            if let Value::Object(map) = &mut v {
+                map.insert(key.to_string(), value);
+            }
+            Ok(v)
+        }
+        Err(mut e) => {
+            if let Some(Value::Object(map)) = &mut e.data {
+                map.insert(key.to_string(), value);
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Tells the transaction and log subscribers about a committed transaction.
+fn publish_transaction(state: &ServerState, id: u64, receipt: &Receipt) {
+    let record = match state.history.read().get(id) {
+        Some(record) => record.to_json(),
+        None => return,
+    };
+
+    let mut subscriptions = state.subscriptions.write();
+    subscriptions.notify(&Topic::Transactions, record);
+    for (level, message) in &receipt.logs {
+        let log = json!({
+            "transaction_id": id,
+            "level": format!("{:?}", level),
+            "message": message
         });
-        config
-    });
+        subscriptions.notify(&Topic::Logs, log);
+    }
+}
 
-    if let Some(r) = receipt {
-        let mut decoded_results = Vec::new();
-        for result in r.results {
-            match result {
-                Ok(v) => {
-                    if let Some(sv) = v {
-                        let bytes = &sv.encoded[..];
-                        let mut vaults: Vec<Vid> = Vec::new();
-                        let lock = LEDGER.read();
-                        let mut err = 0;
-                        //TODO: Let the formatter not depend on ledger, it only needs it to decode
-                        //LazyMaps
-                        let _ = parking_lot::RwLockReadGuard::map(lock, |ledger| {
-                            match formatter::format_data_with_ledger(bytes, ledger, &mut vaults) {
-                                Ok(decoded) => decoded_results.push(decoded),
-                                Err(e) => {
-                                    println!("{:?}", e);
-                                    err = 1;
-                                }
-                            }
-                            ledger
-                        });
-                        if err == 1 {
-                            return decode_err();
-                        }
+/// Stores a committed transaction in the history and returns its id.
+fn record_transaction(
+    state: &ServerState,
+    execution: &Execution,
+    receipt: &Receipt,
+    ledger: &RpcLedger,
+    epoch: u64,
+    nonce: u64,
+) -> u64 {
+    let mut involved = execution.involved.clone();
+    for address in &receipt.new_entities {
+        // The components new_account creates are the accounts
+        involved.add_created(*address, execution.kind == "new_account");
+    }
+
+    state.history.write().record(TransactionRecord {
+        id: 0,
+        kind: execution.kind.to_string(),
+        instructions: receipt
+            .transaction
+            .instructions
+            .iter()
+            .map(|i| format!("{:?}", i))
+            .collect(),
+        signers: execution.signers.clone(),
+        epoch,
+        nonce,
+        success: receipt.success,
+        receipt: scrypto_helpers::receipt_to_json(receipt, ledger, false),
+        // A failed transaction has no results to decode
+        results: decode_results(receipt, ledger, false).unwrap_or(Value::Null),
+        involved,
+    })
+}
+
+/// Decodes the return values of all instructions, the ledger is needed to decode LazyMaps.
+fn decode_results(
+    receipt: &Receipt,
+    ledger: &RpcLedger,
+    display: bool,
+) -> jsonrpc_core::Result<Value> {
+    let mut decoded_results = Vec::new();
+    for result in &receipt.results {
+        match result {
+            Ok(Some(sv)) => {
+                let bytes = &sv.encoded[..];
+                let mut vaults: Vec<Vid> = Vec::new();
+                let decoded = if display {
+                    formatter::format_data_with_ledger(bytes, ledger, &mut vaults).map(|v| json!(v))
+                } else {
+                    formatter::format_data_json_with_ledger(bytes, ledger, &mut vaults)
+                };
+                match decoded {
+                    Ok(decoded) => decoded_results.push(decoded),
+                    Err(e) => {
+                        eprintln!("{:?}", e);
+                        return decode_err();
                     }
                 }
-                Err(e) => return result_err(&e.to_string()),
             }
+            Ok(None) => {}
+            Err(e) => return result_err(&e.to_string()),
         }
-        return Ok(json!(decoded_results));
     }
-    //Should not hit this
-    transaction_err()
+    Ok(json!(decoded_results))
 }
 
-fn get_balance(params: GetBalance) -> jsonrpc_core::Result<Value> {
+fn get_balance(state: &ServerState, params: GetBalance) -> jsonrpc_core::Result<Value> {
     let address: Address;
-    match Address::from_str(&params.address) {
+    match parse_address(state, &params.address, "Component address") {
         Ok(v) => address = v,
-        Err(e) => return invalid_params_err("Component address wrong format"),
+        Err(e) => return Err(e),
     }
 
     if !address.is_component() {
         return invalid_params_err("Addres isn't a component");
     }
 
+    let ledger = state.ledger.read();
+    let mut vids: Vec<Vid> = Vec::new();
+    if let Some(component) = ledger.get_component(address) {
+        if let Ok(component_state) = component.state(SuperUser) {
+            if format_data_with_ledger(&component_state, &*ledger, &mut vids).is_ok() {
+                return Ok(json!(vault_balances(&ledger, vids, params.aggregate)));
+            }
+        }
+    }
+
+    result_err("Can't get amounts for address")
+}
+
+/// Blueprint, package and decoded state of a component, with the contents of its lazy maps and
+/// vaults in place.
+fn get_component_state(
+    state: &ServerState,
+    params: GetComponentState,
+) -> jsonrpc_core::Result<Value> {
+    let address = parse_address(state, &params.address, "Component address")?;
+    if !address.is_component() {
+        return invalid_params_err("Address isn't a component");
+    }
+
+    let ledger = state.ledger.read();
+    let component = match ledger.get_component(address) {
+        Some(component) => component,
+        None => return invalid_params_err("Unknown component"),
+    };
+    let component_state = match component.state(SuperUser) {
+        Ok(v) => v,
+        Err(e) => return result_err(&e.to_string()),
+    };
+
     let mut vids: Vec<Vid> = Vec::new();
-    let mut amounts: Option<HashMap<String, Number>> = None;
-    let lock = LEDGER.read();
-    let _ = parking_lot::RwLockReadGuard::map(lock, |ledger| {
+    match formatter::format_data_json_with_ledger(&component_state, &*ledger, &mut vids) {
+        Ok(decoded) => Ok(json!({
+            "address": address.to_string(),
+            "blueprint": component.blueprint_name(),
+            "package": component.package_address().to_string(),
+            "state": decoded
+        })),
+        Err(e) => {
+            eprintln!("{:?}", e);
+            decode_err()
+        }
+    }
+}
+
+/// Metadata, supply and minting rights of a resource.
+fn get_resource_def(state: &ServerState, params: GetResourceDef) -> jsonrpc_core::Result<Value> {
+    let address = parse_address(state, &params.address, "Resource address")?;
+    if !address.is_resource_def() {
+        return invalid_params_err("Address isn't a resource definition");
+    }
+
+    let resource_def = match state.ledger.read().get_resource_def(address) {
+        Some(resource_def) => resource_def,
+        None => return invalid_params_err("Unknown resource definition"),
+    };
+
+    let (resource_type, granularity) = match resource_def.resource_type() {
+        ResourceType::Fungible { granularity } => ("Fungible", Some(granularity)),
+        ResourceType::NonFungible => ("NonFungible", None),
+    };
+    let metadata: BTreeMap<&String, &String> = resource_def.metadata().iter().collect();
+    // The engine has a single minter badge that is needed to both mint and burn, without one
+    // the supply is fixed
+    let minter = resource_def.minter().map(|badge| badge.to_string());
+
+    Ok(json!({
+        "address": address.to_string(),
+        "resource_type": resource_type,
+        "granularity": granularity,
+        "metadata": metadata,
+        "total_supply": resource_def.supply().to_string(),
+        "mintable": minter.is_some(),
+        "burnable": minter.is_some(),
+        "mint_badge": minter,
+        "burn_badge": minter
+    }))
+}
+
+/// Code size and blueprints of a package.
+fn get_package(state: &ServerState, params: GetPackage) -> jsonrpc_core::Result<Value> {
+    let address = parse_address(state, &params.address, "Package address")?;
+    if !address.is_package() {
+        return invalid_params_err("Address isn't a package");
+    }
+
+    let package = match state.ledger.read().get_package(address) {
+        Some(package) => package,
+        None => return invalid_params_err("Unknown package"),
+    };
+    match scrypto_helpers::blueprint_names(package.code()) {
+        Ok(blueprints) => Ok(json!({
+            "address": address.to_string(),
+            "code_size": package.code().len(),
+            "blueprints": blueprints
+        })),
+        Err(e) => result_err(&e),
+    }
+}
+
+/// Functions and methods of a blueprint, as the package exports them.
+fn get_blueprint_abi(state: &ServerState, params: GetBlueprintAbi) -> jsonrpc_core::Result<Value> {
+    let package = parse_address(state, &params.package, "Package address")?;
+    if !package.is_package() {
+        return invalid_params_err("Address isn't a package");
+    }
+
+    let code = match state.ledger.read().get_package(package) {
+        Some(code) => code,
+        None => return invalid_params_err("Unknown package"),
+    };
+    let known = scrypto_helpers::blueprint_names(code.code()).unwrap_or_default();
+    if !known.contains(&params.blueprint) {
+        return invalid_params_err("Unknown blueprint");
+    }
+
+    with_abi_provider(state, package, |abi_provider| {
+        match abi_provider.export_abi(package, &params.blueprint, false) {
+            Ok(abi) => Ok(json!(abi)),
+            Err(e) => result_err(&e.to_string()),
+        }
+    })
+}
+
+/// Runs `f` with an ABI provider on a ledger that only holds a copy of the package at `address`,
+/// or of the component there and its package. ABIs come from running the package, this keeps
+/// that away from the real ledger without a copy of all of it or taking the write locks.
+fn with_abi_provider<T, F>(state: &ServerState, address: Address, f: F) -> T
+where
+    F: FnOnce(&TransactionExecutor<InMemoryLedger>) -> T,
+{
+    let (epoch, nonce) = state.config.read().load_nonce();
+    let mut scratch = InMemoryLedger::new();
+    {
+        let ledger = state.ledger.read();
+        let mut package = address;
         if let Some(component) = ledger.get_component(address) {
-            if let Ok(state) = component.state(SuperUser) {
-                if let Ok(_) = format_data_with_ledger(&state, ledger, &mut vids) {
-                    amounts = Some(
-                        vids.drain(..)
-                            .map(|vid| get_vault_info(ledger, vid))
-                            .collect(),
-                    );
-                }
+            package = component.package_address();
+            scratch.put_component(address, component);
+        }
+        if let Some(code) = ledger.get_package(package) {
+            scratch.put_package(package, code);
+        }
+    }
+
+    let executor = TransactionExecutor::new(&mut scratch, epoch, nonce);
+    f(&executor)
+}
+
+/// Decoded state and vault balances of a component, None if there is no such component.
+fn component_snapshot(ledger: &RpcLedger, address: Address) -> Option<Value> {
+    let component = ledger.get_component(address)?;
+    let state = component.state(SuperUser).ok()?;
+
+    let mut vids: Vec<Vid> = Vec::new();
+    let decoded = formatter::format_data_json_with_ledger(&state, ledger, &mut vids).ok()?;
+    let balances = vault_balances(ledger, vids, false);
+
+    Some(json!({ "state": decoded, "balances": balances }))
+}
+
+fn snapshot_create(state: &ServerState) -> jsonrpc_core::Result<Value> {
+    // Hold both write locks, in the same order as the transactions do, so the nonce always
+    // matches the ledger it was taken with
+    let config = state.config.write();
+    let ledger = state.ledger.write();
+    let (epoch, nonce) = config.load_nonce();
+    let history = state.history.read().scratch();
+    let mut snapshots = state.snapshots.write();
+    let registry = state.registry.read().scratch();
+
+    let id = snapshots.create(Snapshot::new(
+        ledger.clone(),
+        epoch,
+        nonce,
+        history,
+        registry,
+    ));
+    Ok(json!({ "id": id }))
+}
+
+fn snapshot_restore(state: &ServerState, params: SnapshotId) -> jsonrpc_core::Result<Value> {
+    let mut config = state.config.write();
+    let mut ledger = state.ledger.write();
+    let mut history = state.history.write();
+    let snapshots = state.snapshots.read();
+
+    match snapshots.get(params.id) {
+        Some(snapshot) => {
+            // The transactions since the snapshot touched entities that no longer exist
+            if let Err(e) = ledger
+                .restore(&snapshot.ledger)
+                .and_then(|_| config.restore_nonce(snapshot.epoch, snapshot.nonce))
+                .and_then(|_| history.restore(&snapshot.history))
+                .and_then(|_| state.registry.write().restore(&snapshot.registry))
+            {
+                return result_err(&format!("Can't restore snapshot: {}", e));
             }
+            Ok(json!({
+                "id": params.id,
+                "epoch": snapshot.epoch,
+                "nonce": snapshot.nonce,
+                "transactions": snapshot.history.len()
+            }))
         }
-        ledger
-    });
+        None => invalid_params_err("Unknown snapshot id"),
+    }
+}
+
+fn snapshot_list(state: &ServerState) -> jsonrpc_core::Result<Value> {
+    let snapshots = state.snapshots.read();
+    let list: Vec<Value> = snapshots
+        .iter()
+        .map(|(id, snapshot)| {
+            json!({
+                "id": id,
+                "epoch": snapshot.epoch,
+                "nonce": snapshot.nonce,
+                "transactions": snapshot.history.len(),
+                "created": snapshot.created
+            })
+        })
+        .collect();
+    Ok(json!(list))
+}
 
-    if let Some(amounts) = amounts {
-        return Ok(json!(amounts));
+fn snapshot_delete(state: &ServerState, params: SnapshotId) -> jsonrpc_core::Result<Value> {
+    if state.snapshots.write().delete(params.id) {
+        Ok(json!(true))
     } else {
-        return result_err("Can't get amounts for address");
+        invalid_params_err("Unknown snapshot id")
     }
 }
 
-fn get_vault_info(ledger: &InMemoryLedger, vid: Vid) -> (String, Number) {
-    if let Some(vault) = ledger.get_vault(vid) {
-        if let Ok(amount) = vault.amount(SuperUser) {
-            if let Ok(resource_def_address) = vault.resource_address(SuperUser) {
-                if let Ok(amount_numb) = Number::from_str(&amount.to_string()) {
-                    return (hex::encode(resource_def_address.to_vec()), amount_numb);
-                }
+fn get_transaction(state: &ServerState, params: GetTransaction) -> jsonrpc_core::Result<Value> {
+    match state.history.read().get(params.id) {
+        Some(record) => Ok(record.to_json()),
+        None => invalid_params_err("Unknown transaction id"),
+    }
+}
+
+fn list_transactions(state: &ServerState, params: ListTransactions) -> jsonrpc_core::Result<Value> {
+    let roles = [
+        (Role::Account, &params.account),
+        (Role::Component, &params.component),
+        (Role::Package, &params.package),
+    ];
+    let mut filters: Vec<(Role, Address)> = Vec::new();
+    for (role, filter) in roles {
+        if let Some(address) = filter {
+            filters.push((role, parse_address(state, address, "Filter address")?));
+        }
+    }
+    let limit = params.limit.unwrap_or(50).min(500);
+
+    let history = state.history.read();
+    let matching: Vec<&TransactionRecord> = history
+        .iter()
+        .filter(|r| filters.iter().all(|(role, a)| r.involves(*role, *a)))
+        .filter(|r| params.success.map_or(true, |s| r.success == s))
+        .collect();
+
+    let transactions: Vec<Value> = matching
+        .iter()
+        .skip(params.offset)
+        .take(limit)
+        .map(|r| r.to_json())
+        .collect();
+
+    Ok(json!({
+        "total": matching.len(),
+        "offset": params.offset,
+        "transactions": transactions
+    }))
+}
+
+fn get_named_addresses(state: &ServerState) -> jsonrpc_core::Result<Value> {
+    Ok(state.registry.read().to_json())
+}
+
+fn resolve_name(state: &ServerState, params: ResolveName) -> jsonrpc_core::Result<Value> {
+    match state.registry.read().resolve(&params.name) {
+        Some(address) => Ok(json!({ "name": params.name, "address": address.to_string() })),
+        None => invalid_params_err(&format!("Unknown name {}", params.name)),
+    }
+}
+
+fn register_name(state: &ServerState, params: RegisterName) -> jsonrpc_core::Result<Value> {
+    let address: Address;
+    match parse_address(state, &params.address, "Address") {
+        Ok(v) => address = v,
+        Err(e) => return Err(e),
+    }
+
+    match state.registry.write().register(&params.name, address) {
+        Ok(()) => Ok(json!({ "name": params.name, "address": address.to_string() })),
+        Err(e) => invalid_params_err(&e),
+    }
+}
+
+fn sandbox_create(state: &ServerState, params: SandboxCreate) -> jsonrpc_core::Result<Value> {
+    let sandbox = if params.from_setup {
+        match &*state.setup.read() {
+            Some(snapshot) => {
+                let sandbox = ServerState::new(snapshot.ledger.scratch());
+                // Sandboxes aren't persisted, so this can't fail
+                let _ = sandbox
+                    .config
+                    .write()
+                    .restore_nonce(snapshot.epoch, snapshot.nonce);
+                *sandbox.registry.write() = snapshot.registry.scratch();
+                sandbox
+            }
+            None => return result_err("Server isn't set up yet"),
+        }
+    } else {
+        ServerState::new(RpcLedger::with_bootstrap())
+    };
+
+    // Sandboxes trace like the main server unless a call says otherwise
+    let trace = state.config.read().trace.load(Ordering::SeqCst);
+    sandbox.config.read().trace.store(trace, Ordering::SeqCst);
+
+    let id = state.sandboxes.write().create(sandbox);
+    Ok(json!({ "id": id }))
+}
+
+fn sandbox_delete(state: &ServerState, params: SandboxId) -> jsonrpc_core::Result<Value> {
+    if state.sandboxes.write().delete(params.id) {
+        Ok(json!(true))
+    } else {
+        invalid_params_err("Unknown sandbox id")
+    }
+}
+
+fn sandbox_list(state: &ServerState) -> jsonrpc_core::Result<Value> {
+    let ids: Vec<u64> = state.sandboxes.read().iter().map(|(id, _)| *id).collect();
+    Ok(json!(ids))
+}
+
+/// What the vaults hold, in the order they were found. With `aggregate` the vaults that hold
+/// the same resource are added up into one entry, listing all of them.
+fn vault_balances(ledger: &RpcLedger, vids: Vec<Vid>, aggregate: bool) -> Vec<Value> {
+    let mut entries: Vec<(Address, Decimal, Vec<Vid>)> = Vec::new();
+    for vid in vids {
+        let vault = match ledger.get_vault(vid) {
+            Some(vault) => vault,
+            None => continue,
+        };
+        let (resource_def, amount) =
+            match (vault.resource_address(SuperUser), vault.amount(SuperUser)) {
+                (Ok(resource_def), Ok(amount)) => (resource_def, amount),
+                _ => continue,
+            };
+
+        match entries
+            .iter_mut()
+            .find(|(address, _, _)| aggregate && *address == resource_def)
+        {
+            Some(entry) => {
+                entry.1 = entry.1 + amount;
+                entry.2.push(vid);
             }
+            None => entries.push((resource_def, amount, vec![vid])),
         }
     }
 
-    //Should never hit this!!!
-    ("deadb33f".to_string(), Number::from_str("-1").unwrap())
+    entries
+        .into_iter()
+        .map(|(resource_def, amount, vids)| {
+            let metadata = ledger
+                .get_resource_def(resource_def)
+                .map(|def| def.metadata().clone())
+                .unwrap_or_default();
+            // Amounts are strings, a JSON number can't hold every Decimal
+            let mut entry = json!({
+                "resource_def": resource_def.to_string(),
+                "name": metadata.get("name"),
+                "symbol": metadata.get("symbol"),
+                "amount": amount.to_string()
+            });
+            if aggregate {
+                let vaults: Vec<String> = vids.into_iter().map(formatter::vault_id).collect();
+                entry["vaults"] = json!(vaults);
+            } else {
+                entry["vault"] = json!(formatter::vault_id(vids[0]));
+            }
+            entry
+        })
+        .collect()
 }
+
 fn invalid_params_err(slice: &str) -> jsonrpc_core::Result<Value> {
     Err(Error {
         code: ErrorCode::InvalidParams,
@@ -347,13 +1561,47 @@ fn result_err(slice: &str) -> jsonrpc_core::Result<Value> {
     })
 }
 
-fn transaction_err() -> jsonrpc_core::Result<Value> {
-    //println!("{:?}", e);
+fn failed_err(receipt: &Receipt, ledger: &RpcLedger) -> jsonrpc_core::Result<Value> {
+    let message = receipt
+        .results
+        .iter()
+        .find_map(|r| r.as_ref().err())
+        .map(|e| e.to_string())
+        .unwrap_or_else(|| "Transaction failed".to_string());
+
     Err(Error {
+        code: ErrorCode::InternalError,
+        message,
+        data: Some(scrypto_helpers::receipt_to_json(receipt, ledger, false)),
+    })
+}
+
+fn transaction_err() -> jsonrpc_core::Result<Value> {
+    Err(transaction_error())
+}
+
+fn transaction_error() -> Error {
+    Error {
         code: ErrorCode::InvalidParams,
         message: "Error while building transaction".to_string(),
         data: None,
-    })
+    }
+}
+
+fn persist_error(e: std::io::Error) -> Error {
+    Error {
+        code: ErrorCode::InternalError,
+        message: format!("Can't persist the transaction: {}", e),
+        data: None,
+    }
+}
+
+fn argument_error(e: arguments::ArgumentError) -> Error {
+    Error {
+        code: ErrorCode::InvalidParams,
+        message: e.message,
+        data: e.data,
+    }
 }
 
 fn parse_err() -> jsonrpc_core::Result<Value> {
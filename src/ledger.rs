@@ -0,0 +1,484 @@
+use super::persist;
+
+use radix_engine::ledger::*;
+use radix_engine::model::*;
+use sbor::{Decode, Encode};
+use scrypto::buffer::*;
+use scrypto::prelude::*;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PACKAGES: &str = "packages";
+const COMPONENTS: &str = "components";
+const LAZY_MAPS: &str = "lazy_maps";
+const RESOURCE_DEFS: &str = "resource_defs";
+const VAULTS: &str = "vaults";
+const DIRS: [&str; 5] = [PACKAGES, COMPONENTS, LAZY_MAPS, RESOURCE_DEFS, VAULTS];
+
+// A flush writes the new tree here and marks it complete before swapping it in
+const NEXT: &str = ".next";
+const OLD: &str = ".old";
+const COMPLETE: &str = "complete";
+
+// Changes of a commit are written here before they're applied
+const JOURNAL: &str = "journal.json";
+// The epoch and nonce, the server reads them back from this file when it resumes
+const CONFIG: &str = "config.json";
+
+/// The ledger the server runs on, either kept in memory or mirrored to disk.
+#[derive(Clone)]
+pub enum RpcLedger {
+    Memory(InMemoryLedger),
+    File(FileLedger),
+}
+
+impl RpcLedger {
+    pub fn with_bootstrap() -> RpcLedger {
+        RpcLedger::Memory(InMemoryLedger::with_bootstrap())
+    }
+
+    /// An in memory copy of the ledger that can be thrown away, changes to it never hit the disk.
+    pub fn scratch(&self) -> RpcLedger {
+        match self {
+            RpcLedger::Memory(l) => RpcLedger::Memory(l.clone()),
+            RpcLedger::File(l) => RpcLedger::Memory(l.to_memory()),
+        }
+    }
+
+    /// Makes what the last transaction put durable, together with the epoch and nonce it left.
+    pub fn commit(&mut self, epoch: u64, nonce: u64) -> std::io::Result<()> {
+        match self {
+            RpcLedger::Memory(_) => Ok(()),
+            RpcLedger::File(l) => l.commit(Some((epoch, nonce))),
+        }
+    }
+
+    /// Drops what was put since the last commit, an in memory ledger has nothing to drop.
+    pub fn discard(&mut self) {
+        if let RpcLedger::File(l) = self {
+            l.discard();
+        }
+    }
+
+    /// Replaces the whole ledger with a previously taken copy.
+    pub fn restore(&mut self, snapshot: &RpcLedger) -> std::io::Result<()> {
+        *self = snapshot.clone();
+        if let RpcLedger::File(l) = self {
+            l.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Ledger for RpcLedger {
+    fn get_package(&self, address: Address) -> Option<Package> {
+        match self {
+            RpcLedger::Memory(l) => l.get_package(address),
+            RpcLedger::File(l) => l.get_package(address),
+        }
+    }
+
+    fn put_package(&mut self, address: Address, package: Package) {
+        match self {
+            RpcLedger::Memory(l) => l.put_package(address, package),
+            RpcLedger::File(l) => l.put_package(address, package),
+        }
+    }
+
+    fn get_component(&self, address: Address) -> Option<Component> {
+        match self {
+            RpcLedger::Memory(l) => l.get_component(address),
+            RpcLedger::File(l) => l.get_component(address),
+        }
+    }
+
+    fn put_component(&mut self, address: Address, component: Component) {
+        match self {
+            RpcLedger::Memory(l) => l.put_component(address, component),
+            RpcLedger::File(l) => l.put_component(address, component),
+        }
+    }
+
+    fn get_lazy_map(&self, mid: Mid) -> Option<LazyMap> {
+        match self {
+            RpcLedger::Memory(l) => l.get_lazy_map(mid),
+            RpcLedger::File(l) => l.get_lazy_map(mid),
+        }
+    }
+
+    fn put_lazy_map(&mut self, mid: Mid, lazy_map: LazyMap) {
+        match self {
+            RpcLedger::Memory(l) => l.put_lazy_map(mid, lazy_map),
+            RpcLedger::File(l) => l.put_lazy_map(mid, lazy_map),
+        }
+    }
+
+    fn get_resource_def(&self, address: Address) -> Option<ResourceDef> {
+        match self {
+            RpcLedger::Memory(l) => l.get_resource_def(address),
+            RpcLedger::File(l) => l.get_resource_def(address),
+        }
+    }
+
+    fn put_resource_def(&mut self, address: Address, resource_def: ResourceDef) {
+        match self {
+            RpcLedger::Memory(l) => l.put_resource_def(address, resource_def),
+            RpcLedger::File(l) => l.put_resource_def(address, resource_def),
+        }
+    }
+
+    fn get_vault(&self, vid: Vid) -> Option<Vault> {
+        match self {
+            RpcLedger::Memory(l) => l.get_vault(vid),
+            RpcLedger::File(l) => l.get_vault(vid),
+        }
+    }
+
+    fn put_vault(&mut self, vid: Vid, vault: Vault) {
+        match self {
+            RpcLedger::Memory(l) => l.put_vault(vid, vault),
+            RpcLedger::File(l) => l.put_vault(vid, vault),
+        }
+    }
+}
+
+/// A ledger that keeps every substate in memory and writes it through to a directory, one file
+/// per entry. Opening an existing directory restores the ledger exactly as it was left.
+///
+/// What a transaction puts is only staged, `commit` writes all of it together with the epoch and
+/// nonce as one unit, so a crash never leaves half a transaction on disk.
+#[derive(Clone)]
+pub struct FileLedger {
+    root: PathBuf,
+    committed: Substates,
+    // Put since the last commit, boxed so the ledger stays as small as the in memory one
+    staged: Box<Substates>,
+}
+
+/// Substates by kind, laid out like the directories they're stored in.
+#[derive(Clone, Default)]
+struct Substates {
+    packages: HashMap<Address, Package>,
+    components: HashMap<Address, Component>,
+    lazy_maps: HashMap<Mid, LazyMap>,
+    resource_defs: HashMap<Address, ResourceDef>,
+    vaults: HashMap<Vid, Vault>,
+}
+
+/// The changes of one commit, written before any of them is applied.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Journal {
+    // Written to config.json along with the entries, so the nonce always matches the ledger
+    epoch_nonce: Option<(u64, u64)>,
+    // Directory, hex encoded key and hex encoded value of every entry
+    entries: Vec<(String, String, String)>,
+}
+
+impl Substates {
+    fn is_empty(&self) -> bool {
+        self.packages.is_empty()
+            && self.components.is_empty()
+            && self.lazy_maps.is_empty()
+            && self.resource_defs.is_empty()
+            && self.vaults.is_empty()
+    }
+
+    fn merge(&mut self, other: Substates) {
+        self.packages.extend(other.packages);
+        self.components.extend(other.components);
+        self.lazy_maps.extend(other.lazy_maps);
+        self.resource_defs.extend(other.resource_defs);
+        self.vaults.extend(other.vaults);
+    }
+
+    fn entries(&self) -> Vec<(String, String, String)> {
+        let mut entries = Vec::new();
+        entries.extend(self.packages.iter().map(|(k, v)| entry(PACKAGES, k, v)));
+        entries.extend(self.components.iter().map(|(k, v)| entry(COMPONENTS, k, v)));
+        entries.extend(self.lazy_maps.iter().map(|(k, v)| entry(LAZY_MAPS, k, v)));
+        entries.extend(
+            self.resource_defs
+                .iter()
+                .map(|(k, v)| entry(RESOURCE_DEFS, k, v)),
+        );
+        entries.extend(self.vaults.iter().map(|(k, v)| entry(VAULTS, k, v)));
+        entries
+    }
+}
+
+impl FileLedger {
+    /// Opens the ledger stored at `root`, creating an empty one if there is none.
+    pub fn open<P: AsRef<Path>>(root: P) -> std::io::Result<FileLedger> {
+        let root = root.as_ref().to_path_buf();
+        finish_flush(&root)?;
+        for dir in DIRS {
+            fs::create_dir_all(root.join(dir))?;
+        }
+        replay_journal(&root)?;
+
+        Ok(FileLedger {
+            committed: Substates {
+                packages: read_all(&root.join(PACKAGES))?,
+                components: read_all(&root.join(COMPONENTS))?,
+                lazy_maps: read_all(&root.join(LAZY_MAPS))?,
+                resource_defs: read_all(&root.join(RESOURCE_DEFS))?,
+                vaults: read_all(&root.join(VAULTS))?,
+            },
+            staged: Box::default(),
+            root,
+        })
+    }
+
+    pub fn with_bootstrap<P: AsRef<Path>>(root: P) -> std::io::Result<FileLedger> {
+        let mut ledger = FileLedger::open(root)?;
+        if ledger.get_package(SYSTEM_PACKAGE).is_none() {
+            ledger.bootstrap();
+            // Without a nonce, so there's still no previous state to resume from
+            ledger.commit(None)?;
+        }
+        Ok(ledger)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn to_memory(&self) -> InMemoryLedger {
+        let mut ledger = InMemoryLedger::new();
+        for substates in [&self.committed, &*self.staged] {
+            for (k, v) in &substates.packages {
+                ledger.put_package(*k, v.clone());
+            }
+            for (k, v) in &substates.components {
+                ledger.put_component(*k, v.clone());
+            }
+            for (k, v) in &substates.lazy_maps {
+                ledger.put_lazy_map(*k, v.clone());
+            }
+            for (k, v) in &substates.resource_defs {
+                ledger.put_resource_def(*k, v.clone());
+            }
+            for (k, v) in &substates.vaults {
+                ledger.put_vault(*k, v.clone());
+            }
+        }
+        ledger
+    }
+
+    /// Writes everything put since the last commit as one unit, together with the epoch and
+    /// nonce when given. The changes go to a journal first, a crash before it's complete loses
+    /// all of them and one after it is finished when the ledger is opened again. If the journal
+    /// can't be written the changes are dropped, so memory still matches the disk.
+    pub fn commit(&mut self, epoch_nonce: Option<(u64, u64)>) -> std::io::Result<()> {
+        let staged = std::mem::take(&mut self.staged);
+        if staged.is_empty() && epoch_nonce.is_none() {
+            return Ok(());
+        }
+
+        // A journal that couldn't be applied before goes first, it must not be overwritten
+        replay_journal(&self.root)?;
+        let journal = Journal {
+            epoch_nonce,
+            entries: staged.entries(),
+        };
+        let bytes = jsonrpc_core::serde_json::to_vec(&journal)?;
+        persist::write_atomic(&self.root.join(JOURNAL), &bytes)?;
+        self.committed.merge(*staged);
+
+        // From here on the changes are safe, applying them is retried until it works
+        if let Err(e) = replay_journal(&self.root) {
+            eprintln!("Ledger journal not applied yet, it will be retried: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Drops everything put since the last commit.
+    pub fn discard(&mut self) {
+        *self.staged = Substates::default();
+    }
+
+    /// Rewrites the whole directory from memory, used after the maps were swapped wholesale. The
+    /// new tree is written next to the current one and only swapped in once it's complete, so a
+    /// crash leaves either the old or the new ledger.
+    fn flush(&mut self) -> std::io::Result<()> {
+        let staged = std::mem::take(&mut self.staged);
+        self.committed.merge(*staged);
+
+        let next = self.root.join(NEXT);
+        if next.exists() {
+            fs::remove_dir_all(&next)?;
+        }
+        for dir in DIRS {
+            fs::create_dir_all(next.join(dir))?;
+        }
+
+        for (dir, key, value) in self.committed.entries() {
+            let value = hex::decode(value).expect("Entries are hex encoded");
+            persist::write_atomic(&next.join(dir).join(key), &value)?;
+        }
+
+        persist::write_atomic(&next.join(COMPLETE), &[])?;
+        finish_flush(&self.root)
+    }
+}
+
+fn entry<K: Encode, V: Encode>(dir: &str, key: &K, value: &V) -> (String, String, String) {
+    (
+        dir.to_string(),
+        hex::encode(scrypto_encode(key)),
+        hex::encode(scrypto_encode(value)),
+    )
+}
+
+/// Applies a journal left by `commit` and removes it, nothing happens if there is none. Applying
+/// it again after an interruption gives the same result.
+fn replay_journal(root: &Path) -> std::io::Result<()> {
+    let path = root.join(JOURNAL);
+    if !path.exists() {
+        return Ok(());
+    }
+    let corrupted = |e: String| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Corrupted ledger journal {}: {}", path.display(), e),
+        )
+    };
+
+    let journal: Journal = jsonrpc_core::serde_json::from_slice(&fs::read(&path)?)
+        .map_err(|e| corrupted(e.to_string()))?;
+    for (dir, key, value) in &journal.entries {
+        if !DIRS.contains(&dir.as_str()) {
+            return Err(corrupted(format!("unknown directory {}", dir)));
+        }
+        let value = hex::decode(value).map_err(|e| corrupted(e.to_string()))?;
+        persist::write_atomic(&root.join(dir).join(key), &value)?;
+    }
+    if let Some((epoch, nonce)) = journal.epoch_nonce {
+        let config = jsonrpc_core::serde_json::json!({ "epoch": epoch, "nonce": nonce });
+        persist::write_atomic(&root.join(CONFIG), config.to_string().as_bytes())?;
+    }
+    fs::remove_file(&path)
+}
+
+/// Swaps a completely written flush in, or throws away one that was cut short. Running it again
+/// after it was interrupted picks up where it stopped.
+fn finish_flush(root: &Path) -> std::io::Result<()> {
+    let next = root.join(NEXT);
+    let old = root.join(OLD);
+    if !next.exists() {
+        return Ok(());
+    }
+    if !next.join(COMPLETE).exists() {
+        return fs::remove_dir_all(&next);
+    }
+
+    fs::create_dir_all(&old)?;
+    for dir in DIRS {
+        if !next.join(dir).exists() {
+            // Already swapped in before the interruption
+            continue;
+        }
+        if root.join(dir).exists() {
+            if old.join(dir).exists() {
+                fs::remove_dir_all(old.join(dir))?;
+            }
+            fs::rename(root.join(dir), old.join(dir))?;
+        }
+        fs::rename(next.join(dir), root.join(dir))?;
+    }
+    fs::remove_dir_all(&old)?;
+    fs::remove_dir_all(&next)
+}
+
+/// Reads every entry of a ledger directory, the file names are the hex encoded keys.
+fn read_all<K, V>(dir: &Path) -> std::io::Result<HashMap<K, V>>
+where
+    K: Decode + Eq + std::hash::Hash,
+    V: Decode,
+{
+    let mut map = HashMap::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if persist::is_temp(&path) {
+            // An entry that was never completely written, the previous version is still there
+            fs::remove_file(&path)?;
+            continue;
+        }
+        let key = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| hex::decode(name).ok())
+            .and_then(|bytes| scrypto_decode::<K>(&bytes).ok());
+        let value = scrypto_decode::<V>(&fs::read(&path)?).ok();
+
+        match (key, value) {
+            (Some(k), Some(v)) => {
+                map.insert(k, v);
+            }
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Corrupted ledger entry {:?}", path),
+                ))
+            }
+        }
+    }
+    Ok(map)
+}
+
+impl Ledger for FileLedger {
+    fn get_package(&self, address: Address) -> Option<Package> {
+        let staged = self.staged.packages.get(&address);
+        staged
+            .or_else(|| self.committed.packages.get(&address))
+            .cloned()
+    }
+
+    fn put_package(&mut self, address: Address, package: Package) {
+        self.staged.packages.insert(address, package);
+    }
+
+    fn get_component(&self, address: Address) -> Option<Component> {
+        let staged = self.staged.components.get(&address);
+        staged
+            .or_else(|| self.committed.components.get(&address))
+            .cloned()
+    }
+
+    fn put_component(&mut self, address: Address, component: Component) {
+        self.staged.components.insert(address, component);
+    }
+
+    fn get_lazy_map(&self, mid: Mid) -> Option<LazyMap> {
+        let staged = self.staged.lazy_maps.get(&mid);
+        staged
+            .or_else(|| self.committed.lazy_maps.get(&mid))
+            .cloned()
+    }
+
+    fn put_lazy_map(&mut self, mid: Mid, lazy_map: LazyMap) {
+        self.staged.lazy_maps.insert(mid, lazy_map);
+    }
+
+    fn get_resource_def(&self, address: Address) -> Option<ResourceDef> {
+        let staged = self.staged.resource_defs.get(&address);
+        staged
+            .or_else(|| self.committed.resource_defs.get(&address))
+            .cloned()
+    }
+
+    fn put_resource_def(&mut self, address: Address, resource_def: ResourceDef) {
+        self.staged.resource_defs.insert(address, resource_def);
+    }
+
+    fn get_vault(&self, vid: Vid) -> Option<Vault> {
+        let staged = self.staged.vaults.get(&vid);
+        staged.or_else(|| self.committed.vaults.get(&vid)).cloned()
+    }
+
+    fn put_vault(&mut self, vid: Vid, vault: Vault) {
+        self.staged.vaults.insert(vid, vault);
+    }
+}
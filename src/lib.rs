@@ -0,0 +1,22 @@
+mod arguments;
+mod config;
+mod export;
+mod formatter;
+mod history;
+mod json_rpc_thread;
+pub mod ledger;
+mod manifest;
+mod manifest_parser;
+mod persist;
+mod registry;
+mod sandbox;
+mod scrypto_helpers;
+mod server;
+mod setup;
+mod snapshot;
+mod state;
+mod subscriptions;
+mod trace;
+
+pub use server::{RadRpcServer, ServerHandle};
+pub use setup::create_setup_file_example;
@@ -1,32 +1,67 @@
-#[macro_use]
-extern crate lazy_static;
-
-use parking_lot::RwLock;
-use radix_engine::ledger::*;
-use std::sync::Arc;
-
-mod config;
-mod formatter;
-mod json_rpc_thread;
-mod scrypto_helpers;
-mod setup;
-
-lazy_static! {
-    static ref LEDGER: Arc<RwLock<InMemoryLedger>> =
-        Arc::new(RwLock::new(InMemoryLedger::with_bootstrap()));
-    static ref CONFIG: Arc<RwLock<config::Config>> = Arc::new(RwLock::new(config::Config::new()));
-}
+use clap::Parser;
+use rad_rpc::RadRpcServer;
+
+mod cli;
 
 fn main() {
-    //Change the path to the wasm files that it needs to publish in setup::create_setup_file
-    setup::create_setup_file_example();
-    setup::run_setup();
+    let args = cli::Args::parse();
+
+    if args.write_example_config {
+        //Change the path to the wasm files that it needs to publish in setup::create_setup_file
+        rad_rpc::create_setup_file_example(&args.config);
+        eprintln!("Example setup written to {}", args.config.display());
+        return;
+    }
 
-    println!("Spawning HTTP Server");
+    let mut server = RadRpcServer::new()
+        .trace(args.trace)
+        .http(args.bind)
+        .threads(args.threads)
+        .cors_origins(args.cors_origin.clone())
+        .ws(args.with_port(args.ws_port, args.no_ws))
+        .tcp(args.with_port(args.tcp_port, args.no_tcp))
+        .ipc(args.ipc_path.clone());
 
-    let handle = std::thread::spawn(|| {
-        json_rpc_thread::rpc_thread();
-    });
+    if let Some(dir) = &args.ledger_dir {
+        server = server.ledger_dir(dir);
+    }
+    if args.no_setup {
+        eprintln!("Skipping setup");
+    } else {
+        server = server.setup_file(&args.config);
+    }
+
+    // In stdio mode stdout carries the JSON-RPC responses, so all logging goes to stderr
+    if args.stdio {
+        eprintln!("Serving JSON-RPC over stdio");
+        if let Err(e) = server.serve_stdio() {
+            exit_with(e);
+        }
+        return;
+    }
+
+    eprintln!("Spawning RPC servers");
+    match server.start() {
+        Ok(handle) => {
+            // With port 0 the addresses are only known now
+            eprintln!("HTTP listening on {}", handle.http_address());
+            if let Some(address) = handle.ws_address() {
+                eprintln!("WebSocket listening on {}", address);
+            }
+            if let Some(address) = handle.tcp_address() {
+                eprintln!("TCP listening on {}", address);
+            }
+            if let Some(path) = handle.ipc_path() {
+                eprintln!("IPC listening on {}", path);
+            }
+            handle.wait()
+        }
+        Err(e) => exit_with(e),
+    }
+}
 
-    let _ = handle.join();
+/// Startup errors like a corrupted ledger directory are the user's to fix, so no panic.
+fn exit_with(e: std::io::Error) {
+    eprintln!("Unable to start RPC server: {}", e);
+    std::process::exit(1);
 }
@@ -0,0 +1,252 @@
+use super::history::Involved;
+use super::registry::Registry;
+
+use jsonrpc_core::serde::Deserialize;
+use radix_engine::transaction::*;
+use scrypto::prelude::*;
+
+/// One instruction of a transaction manifest as it's sent over JSON-RPC, tagged by the
+/// `instruction` field.
+///
+/// Bucket and BucketRef arguments are given as `"amount,resource_address"`, they are taken from
+/// the resources already in the transaction, or withdrawn from `account` when it is given.
+/// Addresses can be given as a registered `@name`, so can arguments that are exactly `@name` and
+/// the resource of bucket arguments.
+///
+/// Creating a BucketRef as an instruction of its own is not supported. The builder calls used
+/// here only take string arguments, so a ref made by one instruction couldn't be handed to the
+/// next; building it on the raw instructions of the engine hasn't been done. Each BucketRef
+/// argument borrows its own ref from the transaction's resources instead.
+#[derive(Deserialize, Clone)]
+#[serde(tag = "instruction", rename_all = "snake_case")]
+pub enum ManifestInstruction {
+    Withdraw {
+        account: String,
+        amount: String,
+        resource: String,
+    },
+    CallFunction {
+        package: String,
+        blueprint: String,
+        function: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default)]
+        account: Option<String>,
+    },
+    CallMethod {
+        component: String,
+        method: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default)]
+        account: Option<String>,
+    },
+    CallMethodWithAllResources {
+        component: String,
+        method: String,
+    },
+    DropAllBucketRefs,
+    DepositAllBuckets {
+        account: String,
+    },
+}
+
+/// A manifest instruction with all its addresses parsed.
+pub enum Step {
+    Withdraw {
+        account: Address,
+        amount: String,
+        resource: Address,
+    },
+    CallFunction {
+        package: Address,
+        blueprint: String,
+        function: String,
+        args: Vec<String>,
+        account: Option<Address>,
+    },
+    CallMethod {
+        component: Address,
+        method: String,
+        args: Vec<String>,
+        account: Option<Address>,
+    },
+    CallMethodWithAllResources {
+        component: Address,
+        method: String,
+    },
+    DropAllBucketRefs,
+    DepositAllBuckets {
+        account: Address,
+    },
+}
+
+impl ManifestInstruction {
+    pub fn resolve(self, names: &Registry) -> Result<Step, String> {
+        match self {
+            ManifestInstruction::Withdraw {
+                account,
+                amount,
+                resource,
+            } => Ok(Step::Withdraw {
+                account: parse_address(&account, "account", names)?,
+                amount,
+                resource: parse_address(&resource, "resource", names)?,
+            }),
+            ManifestInstruction::CallFunction {
+                package,
+                blueprint,
+                function,
+                args,
+                account,
+            } => Ok(Step::CallFunction {
+                package: parse_address(&package, "package", names)?,
+                blueprint,
+                function,
+                args: names.resolve_args(args)?,
+                account: parse_optional_address(&account, "account", names)?,
+            }),
+            ManifestInstruction::CallMethod {
+                component,
+                method,
+                args,
+                account,
+            } => Ok(Step::CallMethod {
+                component: parse_address(&component, "component", names)?,
+                method,
+                args: names.resolve_args(args)?,
+                account: parse_optional_address(&account, "account", names)?,
+            }),
+            ManifestInstruction::CallMethodWithAllResources { component, method } => {
+                Ok(Step::CallMethodWithAllResources {
+                    component: parse_address(&component, "component", names)?,
+                    method,
+                })
+            }
+            ManifestInstruction::DropAllBucketRefs => Ok(Step::DropAllBucketRefs),
+            ManifestInstruction::DepositAllBuckets { account } => Ok(Step::DepositAllBuckets {
+                account: parse_address(&account, "account", names)?,
+            }),
+        }
+    }
+}
+
+impl Step {
+    /// Every address the step refers to, by the role it plays.
+    pub fn involved(&self) -> Involved {
+        let mut involved = Involved::default();
+        match self {
+            Step::Withdraw {
+                account, resource, ..
+            } => {
+                involved.accounts.push(*account);
+                involved.resources.push(*resource);
+            }
+            Step::CallFunction {
+                package, account, ..
+            } => {
+                involved.packages.push(*package);
+                involved.accounts.extend(*account);
+            }
+            Step::CallMethod {
+                component, account, ..
+            } => {
+                involved.components.push(*component);
+                involved.accounts.extend(*account);
+            }
+            Step::CallMethodWithAllResources { component, .. } => {
+                involved.components.push(*component)
+            }
+            Step::DropAllBucketRefs => {}
+            Step::DepositAllBuckets { account } => involved.accounts.push(*account),
+        }
+        involved
+    }
+}
+
+/// Resolves all instructions, errors name the index of the instruction that is wrong.
+pub fn resolve_all(
+    instructions: Vec<ManifestInstruction>,
+    names: &Registry,
+) -> Result<Vec<Step>, String> {
+    instructions
+        .into_iter()
+        .enumerate()
+        .map(|(i, instruction)| {
+            instruction
+                .resolve(names)
+                .map_err(|e| format!("Instruction {}: {}", i, e))
+        })
+        .collect()
+}
+
+/// Adds all steps to the builder in order and builds the transaction.
+pub fn build_transaction<A: AbiProvider>(
+    abi_provider: &A,
+    steps: Vec<Step>,
+    signers: Vec<Address>,
+) -> Result<Transaction, BuildTransactionError> {
+    let mut builder = TransactionBuilder::new(abi_provider);
+    for step in steps {
+        match step {
+            Step::Withdraw {
+                account,
+                amount,
+                resource,
+            } => {
+                // The engine has no withdraw instruction, taking resources out of an account is
+                // a call to its withdraw method. The bucket it returns ends up with the
+                // transaction's resources
+                builder.call_method(
+                    account,
+                    "withdraw",
+                    vec![amount, resource.to_string()],
+                    None,
+                );
+            }
+            Step::CallFunction {
+                package,
+                blueprint,
+                function,
+                args,
+                account,
+            } => {
+                builder.call_function(package, &blueprint, &function, args, account);
+            }
+            Step::CallMethod {
+                component,
+                method,
+                args,
+                account,
+            } => {
+                builder.call_method(component, &method, args, account);
+            }
+            Step::CallMethodWithAllResources { component, method } => {
+                builder.call_method_with_all_resources(component, &method);
+            }
+            Step::DropAllBucketRefs => {
+                builder.drop_all_bucket_refs();
+            }
+            Step::DepositAllBuckets { account } => {
+                builder.deposit_all_buckets(account);
+            }
+        }
+    }
+    builder.build(signers)
+}
+
+fn parse_address(address: &str, what: &str, names: &Registry) -> Result<Address, String> {
+    names.parse_address(address, &format!("{} address", what))
+}
+
+fn parse_optional_address(
+    address: &Option<String>,
+    what: &str,
+    names: &Registry,
+) -> Result<Option<Address>, String> {
+    match address {
+        Some(address) => parse_address(address, what, names).map(Some),
+        None => Ok(None),
+    }
+}
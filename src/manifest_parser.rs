@@ -0,0 +1,664 @@
+use super::manifest::{ManifestInstruction, Step};
+use super::registry::Registry;
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// A manifest error, lines and columns start at 1.
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+enum Token {
+    // "quoted string"
+    Str(String),
+    // Unquoted word, commands and plain values like numbers and addresses
+    Word(String),
+    // $name
+    Var(String),
+    // Bucket("name")
+    Bucket(String),
+    Equals,
+    Semicolon,
+}
+
+struct Spanned {
+    token: Token,
+    column: usize,
+}
+
+/// Parses a text manifest into steps, one statement per line:
+///
+/// ```text
+/// # Comments start with a hash
+/// LET xrd = "030000000000000000000000000000000000000000000000000004";
+/// WITHDRAW $account "10" $xrd;
+/// TAKE_FROM_WORKTOP "10" $xrd Bucket("payment");
+/// CALL_METHOD $machine "buy_gumball" Bucket("payment");
+/// CALL_FUNCTION $package "GumballMachine" "new" "0.5";
+/// CALL_METHOD_WITH_ALL_RESOURCES $account "deposit_batch";
+/// DROP_ALL_BUCKET_REFS;
+/// DEPOSIT_ALL_BUCKETS $account;
+/// ```
+///
+/// `$name` refers to a `LET` earlier in the manifest or to one of the given `variables`, `@name`
+/// to an address in `names`. A `TAKE_FROM_WORKTOP` names an amount of a resource, passing
+/// `Bucket("name")` as an argument takes that amount from the resources the transaction holds.
+/// A bucket can only be passed once, like the resources it holds.
+/// The trailing `;` is optional and `DEPOSIT` is short for `DEPOSIT_ALL_BUCKETS`.
+pub fn parse_manifest(
+    text: &str,
+    variables: &HashMap<String, String>,
+    names: &Registry,
+) -> Result<Vec<Step>, ParseError> {
+    let mut parser = Parser {
+        names,
+        variables: variables.clone(),
+        buckets: HashMap::new(),
+        line: 0,
+    };
+
+    let mut steps = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        parser.line = i + 1;
+        let mut tokens = tokenize(line).map_err(|(column, message)| parser.err(column, message))?;
+
+        if let Some(Spanned {
+            token: Token::Semicolon,
+            ..
+        }) = tokens.last()
+        {
+            tokens.pop();
+        }
+        if tokens.is_empty() {
+            continue;
+        }
+
+        if let Some(instruction) = parser.statement(&tokens, line)? {
+            let column = tokens[0].column;
+            let step = instruction
+                .resolve(names)
+                .map_err(|e| parser.err(column, e))?;
+            steps.push(step);
+        }
+    }
+    Ok(steps)
+}
+
+struct Parser<'a> {
+    names: &'a Registry,
+    variables: HashMap<String, String>,
+    buckets: HashMap<String, Bucket>,
+    line: usize,
+}
+
+struct Bucket {
+    // "amount,resource", the form bucket arguments are given in
+    value: String,
+    // Line and column it was passed at, it's empty from then on
+    used_at: Option<(usize, usize)>,
+}
+
+impl Parser<'_> {
+    fn err<S: Into<String>>(&self, column: usize, message: S) -> ParseError {
+        ParseError {
+            line: self.line,
+            column,
+            message: message.into(),
+        }
+    }
+
+    /// Parses one statement, returns None for statements that only name things.
+    fn statement(
+        &mut self,
+        tokens: &[Spanned],
+        line: &str,
+    ) -> Result<Option<ManifestInstruction>, ParseError> {
+        let command = match &tokens[0].token {
+            Token::Word(w) => w.as_str(),
+            _ => return Err(self.err(tokens[0].column, "Expected a command")),
+        };
+        let args = &tokens[1..];
+        // Errors about missing arguments point just past the end of the line
+        let end = line.trim_end().chars().count() + 1;
+
+        match command {
+            "LET" => {
+                let name = match args.first().map(|t| &t.token) {
+                    Some(Token::Word(name)) => name.clone(),
+                    _ => return Err(self.err(self.column(args, 0, end), "Expected a name")),
+                };
+                if !matches!(args.get(1).map(|t| &t.token), Some(Token::Equals)) {
+                    return Err(self.err(self.column(args, 1, end), "Expected ="));
+                }
+                let value = self.value(args, 2, end)?;
+                self.expect_end(args, 3)?;
+                self.variables.insert(name, value);
+                Ok(None)
+            }
+            "TAKE_FROM_WORKTOP" => {
+                let amount = self.value(args, 0, end)?;
+                let resource = self.address(args, 1, end, "resource")?;
+                let name = match args.get(2).map(|t| &t.token) {
+                    Some(Token::Bucket(name)) => name.clone(),
+                    _ => {
+                        return Err(self.err(self.column(args, 2, end), "Expected Bucket(\"name\")"))
+                    }
+                };
+                self.expect_end(args, 3)?;
+                if self.buckets.contains_key(&name) {
+                    return Err(self.err(args[2].column, format!("Bucket {} already exists", name)));
+                }
+                self.buckets.insert(
+                    name,
+                    Bucket {
+                        value: format!("{},{}", amount, resource),
+                        used_at: None,
+                    },
+                );
+                Ok(None)
+            }
+            "WITHDRAW" => {
+                let instruction = ManifestInstruction::Withdraw {
+                    account: self.address(args, 0, end, "account")?,
+                    amount: self.value(args, 1, end)?,
+                    resource: self.address(args, 2, end, "resource")?,
+                };
+                self.expect_end(args, 3)?;
+                Ok(Some(instruction))
+            }
+            "CALL_FUNCTION" => Ok(Some(ManifestInstruction::CallFunction {
+                package: self.address(args, 0, end, "package")?,
+                blueprint: self.value(args, 1, end)?,
+                function: self.value(args, 2, end)?,
+                args: self.values(&args[3.min(args.len())..])?,
+                account: None,
+            })),
+            "CALL_METHOD" => Ok(Some(ManifestInstruction::CallMethod {
+                component: self.address(args, 0, end, "component")?,
+                method: self.value(args, 1, end)?,
+                args: self.values(&args[2.min(args.len())..])?,
+                account: None,
+            })),
+            "CALL_METHOD_WITH_ALL_RESOURCES" => {
+                let instruction = ManifestInstruction::CallMethodWithAllResources {
+                    component: self.address(args, 0, end, "component")?,
+                    method: self.value(args, 1, end)?,
+                };
+                self.expect_end(args, 2)?;
+                Ok(Some(instruction))
+            }
+            "DROP_ALL_BUCKET_REFS" => {
+                self.expect_end(args, 0)?;
+                Ok(Some(ManifestInstruction::DropAllBucketRefs))
+            }
+            "DEPOSIT_ALL_BUCKETS" | "DEPOSIT" => {
+                let instruction = ManifestInstruction::DepositAllBuckets {
+                    account: self.address(args, 0, end, "account")?,
+                };
+                self.expect_end(args, 1)?;
+                Ok(Some(instruction))
+            }
+            _ => Err(self.err(tokens[0].column, format!("Unknown command {}", command))),
+        }
+    }
+
+    fn column(&self, args: &[Spanned], i: usize, end: usize) -> usize {
+        args.get(i).map(|t| t.column).unwrap_or(end)
+    }
+
+    /// The value of the argument at `i`, with variables and buckets filled in. A bucket is used
+    /// up by it.
+    fn value(&mut self, args: &[Spanned], i: usize, end: usize) -> Result<String, ParseError> {
+        let arg = match args.get(i) {
+            Some(arg) => arg,
+            None => return Err(self.err(end, "Missing argument")),
+        };
+
+        match &arg.token {
+            Token::Str(s) | Token::Word(s) => Ok(s.clone()),
+            Token::Var(name) => match self.variables.get(name) {
+                Some(v) => Ok(v.clone()),
+                None => Err(self.err(arg.column, format!("Unknown variable ${}", name))),
+            },
+            Token::Bucket(name) => {
+                let line = self.line;
+                match self.buckets.get_mut(name) {
+                    Some(Bucket {
+                        used_at: Some((used_line, used_column)),
+                        ..
+                    }) => {
+                        let message = format!(
+                            "Bucket {} was already used at line {}, column {}",
+                            name, used_line, used_column
+                        );
+                        Err(self.err(arg.column, message))
+                    }
+                    Some(bucket) => {
+                        bucket.used_at = Some((line, arg.column));
+                        Ok(bucket.value.clone())
+                    }
+                    None => Err(self.err(arg.column, format!("Unknown bucket {}", name))),
+                }
+            }
+            Token::Equals => Err(self.err(arg.column, "Unexpected =")),
+            Token::Semicolon => Err(self.err(arg.column, "Unexpected ;")),
+        }
+    }
+
+    /// The argument at `i` as an address. A `@name` is looked up right away, so an unknown one is
+    /// reported where it's written.
+    fn address(
+        &mut self,
+        args: &[Spanned],
+        i: usize,
+        end: usize,
+        what: &str,
+    ) -> Result<String, ParseError> {
+        let value = self.value(args, i, end)?;
+        self.names
+            .parse_address(&value, &format!("{} address", what))
+            .map(|address| address.to_string())
+            .map_err(|e| self.err(args[i].column, e))
+    }
+
+    /// The call arguments, with their names resolved like the ones of `address`.
+    fn values(&mut self, args: &[Spanned]) -> Result<Vec<String>, ParseError> {
+        (0..args.len())
+            .map(|i| {
+                let value = self.value(args, i, 0)?;
+                self.names
+                    .resolve_arg(value)
+                    .map_err(|e| self.err(args[i].column, e))
+            })
+            .collect()
+    }
+
+    fn expect_end(&self, args: &[Spanned], count: usize) -> Result<(), ParseError> {
+        match args.get(count) {
+            Some(arg) => Err(self.err(arg.column, "Too many arguments")),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Splits a line into tokens, errors are returned as (column, message).
+fn tokenize(line: &str) -> Result<Vec<Spanned>, (usize, String)> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let token = match c {
+            '#' => break,
+            ';' => {
+                i += 1;
+                Token::Semicolon
+            }
+            '=' => {
+                i += 1;
+                Token::Equals
+            }
+            '"' => {
+                let (s, next) = read_string(&chars, i)?;
+                i = next;
+                Token::Str(s)
+            }
+            '$' => {
+                let start = i + 1;
+                i = start;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                if i == start {
+                    return Err((column, "Expected a variable name after $".to_string()));
+                }
+                Token::Var(chars[start..i].iter().collect())
+            }
+            _ => {
+                let start = i;
+                while i < chars.len() && !chars[i].is_whitespace() && !"#;=\"()$".contains(chars[i])
+                {
+                    i += 1;
+                }
+                if i == start {
+                    return Err((column, format!("Unexpected {}", c)));
+                }
+                let word: String = chars[start..i].iter().collect();
+
+                if word == "Bucket" {
+                    // Bucket("name")
+                    if chars.get(i) != Some(&'(') {
+                        return Err((i + 1, "Expected (".to_string()));
+                    }
+                    if chars.get(i + 1) != Some(&'"') {
+                        return Err((i + 2, "Expected a bucket name".to_string()));
+                    }
+                    let (name, next) = read_string(&chars, i + 1)?;
+                    if chars.get(next) != Some(&')') {
+                        return Err((next + 1, "Expected )".to_string()));
+                    }
+                    i = next + 1;
+                    Token::Bucket(name)
+                } else {
+                    Token::Word(word)
+                }
+            }
+        };
+        tokens.push(Spanned { token, column });
+    }
+    Ok(tokens)
+}
+
+/// Reads the string starting at the quote at `start`, returns it and the index after the
+/// closing quote.
+fn read_string(chars: &[char], start: usize) -> Result<(String, usize), (usize, String)> {
+    let mut s = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '"' => return Ok((s, i + 1)),
+            '\\' if i + 1 < chars.len() => {
+                s.push(chars[i + 1]);
+                i += 2;
+            }
+            c => {
+                s.push(c);
+                i += 1;
+            }
+        }
+    }
+    Err((start + 1, "Unterminated string".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scrypto::prelude::*;
+
+    const PACKAGE: &str = "010000000000000000000000000000000000000000000000000001";
+    const MACHINE: &str = "020000000000000000000000000000000000000000000000000002";
+    const ACCOUNT: &str = "02b61acea4378e307342b2b684fc35acf0238a4accb9f91e8a4364";
+    const XRD: &str = "030000000000000000000000000000000000000000000000000004";
+
+    fn names() -> Registry {
+        let mut names = Registry::new();
+        names
+            .register("machine", Address::from_str(MACHINE).unwrap())
+            .unwrap();
+        names
+    }
+
+    fn parse(text: &str) -> Result<Vec<Step>, ParseError> {
+        let mut variables = HashMap::new();
+        variables.insert("account".to_string(), ACCOUNT.to_string());
+        parse_manifest(text, &variables, &names())
+    }
+
+    fn parse_one(text: &str) -> Step {
+        let mut steps = parse(text).unwrap();
+        assert_eq!(steps.len(), 1);
+        steps.remove(0)
+    }
+
+    /// Asserts the manifest fails at `line` and `column` with a message starting with `message`.
+    fn assert_error(text: &str, line: usize, column: usize, message: &str) {
+        let e = match parse(text) {
+            Ok(_) => panic!("{:?} parsed", text),
+            Err(e) => e,
+        };
+        assert_eq!((e.line, e.column), (line, column), "{}", e);
+        assert!(e.message.starts_with(message), "{}", e);
+    }
+
+    fn address(s: &str) -> Address {
+        Address::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn let_defines_a_variable() {
+        let text = format!("LET xrd = \"{}\";\nDEPOSIT_ALL_BUCKETS $xrd;", XRD);
+        match parse_one(&text) {
+            Step::DepositAllBuckets { account } => assert_eq!(account, address(XRD)),
+            _ => panic!("wrong step"),
+        }
+    }
+
+    #[test]
+    fn withdraw() {
+        match parse_one(&format!("WITHDRAW $account \"10\" {}", XRD)) {
+            Step::Withdraw {
+                account,
+                amount,
+                resource,
+            } => {
+                assert_eq!(account, address(ACCOUNT));
+                assert_eq!(amount, "10");
+                assert_eq!(resource, address(XRD));
+            }
+            _ => panic!("wrong step"),
+        }
+    }
+
+    #[test]
+    fn take_from_worktop_fills_the_bucket_argument() {
+        let text = format!(
+            "TAKE_FROM_WORKTOP \"10\" {} Bucket(\"payment\");\n\
+             CALL_METHOD @machine \"buy_gumball\" Bucket(\"payment\");",
+            XRD
+        );
+        match parse_one(&text) {
+            Step::CallMethod {
+                component,
+                method,
+                args,
+                account,
+            } => {
+                assert_eq!(component, address(MACHINE));
+                assert_eq!(method, "buy_gumball");
+                assert_eq!(args, vec![format!("10,{}", XRD)]);
+                assert!(account.is_none());
+            }
+            _ => panic!("wrong step"),
+        }
+    }
+
+    #[test]
+    fn call_function() {
+        match parse_one(&format!(
+            "CALL_FUNCTION {} \"GumballMachine\" \"new\" \"0.5\"",
+            PACKAGE
+        )) {
+            Step::CallFunction {
+                package,
+                blueprint,
+                function,
+                args,
+                account,
+            } => {
+                assert_eq!(package, address(PACKAGE));
+                assert_eq!(blueprint, "GumballMachine");
+                assert_eq!(function, "new");
+                assert_eq!(args, vec!["0.5".to_string()]);
+                assert!(account.is_none());
+            }
+            _ => panic!("wrong step"),
+        }
+    }
+
+    #[test]
+    fn call_method_resolves_names_in_arguments() {
+        match parse_one("CALL_METHOD $account \"withdraw\" \"10,@machine\"") {
+            Step::CallMethod { args, .. } => assert_eq!(args, vec![format!("10,{}", MACHINE)]),
+            _ => panic!("wrong step"),
+        }
+    }
+
+    #[test]
+    fn call_method_with_all_resources() {
+        match parse_one("CALL_METHOD_WITH_ALL_RESOURCES $account \"deposit_batch\"") {
+            Step::CallMethodWithAllResources { component, method } => {
+                assert_eq!(component, address(ACCOUNT));
+                assert_eq!(method, "deposit_batch");
+            }
+            _ => panic!("wrong step"),
+        }
+    }
+
+    #[test]
+    fn drop_all_bucket_refs() {
+        assert!(matches!(
+            parse_one("DROP_ALL_BUCKET_REFS;"),
+            Step::DropAllBucketRefs
+        ));
+    }
+
+    #[test]
+    fn deposit_is_short_for_deposit_all_buckets() {
+        match parse_one("DEPOSIT $account") {
+            Step::DepositAllBuckets { account } => assert_eq!(account, address(ACCOUNT)),
+            _ => panic!("wrong step"),
+        }
+    }
+
+    #[test]
+    fn skips_comments_and_empty_lines() {
+        let text = "# Comment\n\n   \nDROP_ALL_BUCKET_REFS # trailing comment\n;";
+        assert_eq!(parse(text).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn reports_tokenizer_errors() {
+        assert_error("WITHDRAW \"10", 1, 10, "Unterminated string");
+        assert_error("DEPOSIT $", 1, 9, "Expected a variable name");
+        assert_error(
+            "CALL_METHOD $account \"a\" Bucket\"b\"",
+            1,
+            32,
+            "Expected (",
+        );
+        assert_error(
+            "CALL_METHOD $account \"a\" Bucket(b)",
+            1,
+            33,
+            "Expected a bucket name",
+        );
+        assert_error(
+            "CALL_METHOD $account \"a\" Bucket(\"b\"",
+            1,
+            36,
+            "Expected )",
+        );
+        assert_error("DEPOSIT )", 1, 9, "Unexpected )");
+    }
+
+    #[test]
+    fn reports_statement_errors() {
+        assert_error("\"DEPOSIT\"", 1, 1, "Expected a command");
+        assert_error(
+            "DROP_ALL_BUCKET_REFS;\n  SEND $account",
+            2,
+            3,
+            "Unknown command",
+        );
+        assert_error("LET \"x\" = 1", 1, 5, "Expected a name");
+        assert_error("LET x 1", 1, 7, "Expected =");
+        assert_error("LET x = 1 2", 1, 11, "Too many arguments");
+        assert_error(
+            "TAKE_FROM_WORKTOP 1 $account \"b\"",
+            1,
+            30,
+            "Expected Bucket",
+        );
+        assert_error("DEPOSIT $nobody", 1, 9, "Unknown variable $nobody");
+        assert_error(
+            "CALL_METHOD $account \"a\" Bucket(\"b\")",
+            1,
+            26,
+            "Unknown bucket b",
+        );
+        assert_error("CALL_METHOD $account =", 1, 22, "Unexpected =");
+        assert_error("DROP_ALL_BUCKET_REFS $account", 1, 22, "Too many arguments");
+        assert_error("DEPOSIT @nobody", 1, 9, "Unknown name @nobody");
+        assert_error(
+            "CALL_METHOD @machine \"a\" \"1\" \"10,@nobody\"",
+            1,
+            30,
+            "Unknown name @nobody",
+        );
+        assert_error(
+            "WITHDRAW $account \"10\" nothing",
+            1,
+            24,
+            "resource address wrong format",
+        );
+    }
+
+    #[test]
+    fn missing_arguments_point_past_the_end_of_the_line() {
+        assert_error("WITHDRAW $account \"10\"  ", 1, 23, "Missing argument");
+        assert_error("DEPOSIT;", 1, 9, "Missing argument");
+        assert_error("\n\nCALL_METHOD $account", 3, 21, "Missing argument");
+    }
+
+    #[test]
+    fn rejects_a_bucket_used_twice() {
+        let text = format!(
+            "TAKE_FROM_WORKTOP \"10\" {} Bucket(\"payment\")\n\
+             CALL_METHOD @machine \"buy_gumball\" Bucket(\"payment\")\n\
+             CALL_METHOD @machine \"buy_gumball\" Bucket(\"payment\")",
+            XRD
+        );
+        assert_error(
+            &text,
+            3,
+            36,
+            "Bucket payment was already used at line 2, column 36",
+        );
+    }
+
+    #[test]
+    fn rejects_a_bucket_passed_twice_in_one_call() {
+        let text = format!(
+            "TAKE_FROM_WORKTOP \"10\" {} Bucket(\"p\")\n\
+             CALL_METHOD @machine \"a\" Bucket(\"p\") Bucket(\"p\")",
+            XRD
+        );
+        assert_error(
+            &text,
+            2,
+            38,
+            "Bucket p was already used at line 2, column 26",
+        );
+    }
+
+    #[test]
+    fn rejects_a_bucket_taken_twice() {
+        let text = format!(
+            "TAKE_FROM_WORKTOP \"1\" {0} Bucket(\"p\")\nTAKE_FROM_WORKTOP \"2\" {0} Bucket(\"p\")",
+            XRD
+        );
+        assert_error(&text, 2, 78, "Bucket p already exists");
+    }
+}
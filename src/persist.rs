@@ -0,0 +1,24 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Replaces the file at `path` without ever leaving it half written. The contents go to a
+/// temporary file in the same directory first, which is then renamed over the old one.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp = temp_path(path);
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/// Whether the file was left behind by a `write_atomic` that never finished.
+pub fn is_temp(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| ext == "tmp")
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
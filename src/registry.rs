@@ -0,0 +1,166 @@
+use super::persist;
+
+use jsonrpc_core::serde_json::{json, Value};
+use scrypto::prelude::*;
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+
+/// Names for addresses, filled from Config.json during the setup and extended over RPC.
+pub struct Registry {
+    names: BTreeMap<String, Address>,
+    // When set the names are written here every time they change
+    path: Option<PathBuf>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry {
+            names: BTreeMap::new(),
+            path: None,
+        }
+    }
+
+    /// Persists the names to `path`, loading the previous ones if the file exists.
+    pub fn persist_to(&mut self, path: PathBuf) -> io::Result<()> {
+        if path.exists() {
+            let corrupted = |e: String| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Corrupted names {}: {}", path.display(), e),
+                )
+            };
+            let file = std::fs::File::open(&path)?;
+            let names: BTreeMap<String, String> = jsonrpc_core::serde_json::from_reader(file)
+                .map_err(|e| corrupted(e.to_string()))?;
+            for (name, address) in names {
+                let address = Address::from_str(&address)
+                    .map_err(|_| corrupted(format!("{} has no valid address", name)))?;
+                self.names.insert(name, address);
+            }
+        }
+        self.path = Some(path);
+        Ok(())
+    }
+
+    /// A copy of the names that is never written to disk.
+    pub fn scratch(&self) -> Registry {
+        Registry {
+            names: self.names.clone(),
+            path: None,
+        }
+    }
+
+    /// Replaces all names with the ones of a snapshot, keeps persisting to the same file.
+    pub fn restore(&mut self, snapshot: &Registry) -> io::Result<()> {
+        self.names = snapshot.names.clone();
+        self.save()
+    }
+
+    /// Names a new address or points an existing name somewhere else.
+    pub fn register(&mut self, name: &str, address: Address) -> Result<(), String> {
+        if !is_valid_name(name) {
+            return Err(format!(
+                "Name {} must only use letters, digits and underscores",
+                name
+            ));
+        }
+        self.names.insert(name.to_string(), address);
+        self.save()
+            .map_err(|e| format!("Name {} registered but not saved: {}", name, e))
+    }
+
+    pub fn resolve(&self, name: &str) -> Option<Address> {
+        self.names.get(name).cloned()
+    }
+
+    /// Parses an address, a `@name` is looked up instead. `what` names the value in the error.
+    pub fn parse_address(&self, value: &str, what: &str) -> Result<Address, String> {
+        match value.strip_prefix('@') {
+            Some(name) => self
+                .resolve(name)
+                .ok_or_else(|| format!("Unknown name @{}", name)),
+            None => Address::from_str(value).map_err(|_| format!("{} wrong format", what)),
+        }
+    }
+
+    /// Replaces arguments that are a `@name` by its address, and the resource of bucket
+    /// arguments like `"10,@Gumballs"`. Any other argument is passed on as it is, also when it
+    /// happens to contain an `@`.
+    pub fn resolve_args(&self, args: Vec<String>) -> Result<Vec<String>, String> {
+        args.into_iter()
+            .enumerate()
+            .map(|(i, arg)| {
+                self.resolve_arg(arg)
+                    .map_err(|e| format!("{} in argument {}", e, i))
+            })
+            .collect()
+    }
+
+    /// Resolves a single argument the way `resolve_args` does.
+    pub fn resolve_arg(&self, arg: String) -> Result<String, String> {
+        let (amount, value) = match arg.split_once(',') {
+            Some((amount, value)) if Decimal::from_str(amount).is_ok() => (Some(amount), value),
+            Some(_) => return Ok(arg),
+            None => (None, arg.as_str()),
+        };
+        let name = match value.strip_prefix('@') {
+            Some(name) if is_valid_name(name) => name,
+            _ => return Ok(arg),
+        };
+
+        let address = self
+            .resolve(name)
+            .ok_or_else(|| format!("Unknown name @{}", name))?;
+        Ok(match amount {
+            Some(amount) => format!("{},{}", amount, address),
+            None => address.to_string(),
+        })
+    }
+
+    /// All names, grouped by what the address is of.
+    pub fn to_json(&self) -> Value {
+        let mut packages = BTreeMap::new();
+        let mut components = BTreeMap::new();
+        let mut resources = BTreeMap::new();
+        let mut keys = BTreeMap::new();
+        for (name, address) in &self.names {
+            let group = if address.is_package() {
+                &mut packages
+            } else if address.is_component() {
+                &mut components
+            } else if address.is_resource_def() {
+                &mut resources
+            } else {
+                &mut keys
+            };
+            group.insert(name.clone(), address.to_string());
+        }
+
+        json!({
+            "packages": packages,
+            "components": components,
+            "resources": resources,
+            "keys": keys
+        })
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if let Some(path) = &self.path {
+            let names: BTreeMap<&String, String> = self
+                .names
+                .iter()
+                .map(|(name, address)| (name, address.to_string()))
+                .collect();
+            let bytes = jsonrpc_core::serde_json::to_vec_pretty(&names)?;
+            persist::write_atomic(path, &bytes)?;
+        }
+        Ok(())
+    }
+}
+
+/// Names follow the same rules as manifest variables.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
@@ -0,0 +1,38 @@
+use super::state::State;
+
+use std::collections::BTreeMap;
+
+/// Isolated servers living next to the main one, each with its own ledger, nonce and history.
+pub struct Sandboxes {
+    next_id: u64,
+    sandboxes: BTreeMap<u64, State>,
+}
+
+impl Sandboxes {
+    pub fn new() -> Sandboxes {
+        Sandboxes {
+            next_id: 0,
+            sandboxes: BTreeMap::new(),
+        }
+    }
+
+    /// Stores a sandbox and returns its id, ids are never reused.
+    pub fn create(&mut self, state: State) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.sandboxes.insert(id, state);
+        id
+    }
+
+    pub fn get(&self, id: u64) -> Option<State> {
+        self.sandboxes.get(&id).cloned()
+    }
+
+    pub fn delete(&mut self, id: u64) -> bool {
+        self.sandboxes.remove(&id).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&u64, &State)> {
+        self.sandboxes.iter()
+    }
+}
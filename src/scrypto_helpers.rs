@@ -1,4 +1,11 @@
-use radix_engine::transaction::Receipt;
+use super::formatter;
+use super::trace;
+
+use jsonrpc_core::serde_json::json;
+use jsonrpc_core::Value;
+use parity_wasm::elements::Internal;
+use radix_engine::ledger::Ledger;
+use radix_engine::transaction::{Receipt, Transaction, TransactionExecutor};
 use scrypto::prelude::*;
 
 pub fn address_to_signers(address: Address) -> Result<Vec<Address>, Box<dyn std::error::Error>> {
@@ -11,7 +18,7 @@ pub fn address_to_signers(address: Address) -> Result<Vec<Address>, Box<dyn std:
     Ok(v)
 }
 
-pub fn get_call_results(receipt: Receipt) -> (Vec<Address>, Vec<Address>, Vec<Address>) {
+pub fn get_call_results(receipt: &Receipt) -> (Vec<Address>, Vec<Address>, Vec<Address>) {
     let packages = receipt
         .new_entities
         .iter()
@@ -35,3 +42,90 @@ pub fn get_call_results(receipt: Receipt) -> (Vec<Address>, Vec<Address>, Vec<Ad
 
     (resources, components, packages)
 }
+
+/// Turns everything in a receipt into JSON, the ledger is only used to decode LazyMaps.
+pub fn receipt_to_json<L: Ledger>(receipt: &Receipt, ledger: &L, display: bool) -> Value {
+    let (resources, components, packages) = get_call_results(receipt);
+    let to_strings = |v: Vec<Address>| -> Vec<String> { v.iter().map(|x| x.to_string()).collect() };
+
+    let instructions: Vec<Value> = receipt
+        .transaction
+        .instructions
+        .iter()
+        .enumerate()
+        .map(|(i, instruction)| {
+            // A failed transaction has no results for the instructions after the failing one
+            let result = match receipt.results.get(i) {
+                Some(Ok(Some(sv))) => {
+                    let mut vaults: Vec<Vid> = Vec::new();
+                    let decoded = if display {
+                        formatter::format_data_with_ledger(&sv.encoded, ledger, &mut vaults)
+                            .map(|v| json!(v))
+                    } else {
+                        formatter::format_data_json_with_ledger(&sv.encoded, ledger, &mut vaults)
+                    };
+                    match decoded {
+                        Ok(v) => json!({ "ok": v }),
+                        Err(e) => json!({ "decode_error": format!("{:?}", e) }),
+                    }
+                }
+                Some(Ok(None)) => json!({ "ok": null }),
+                Some(Err(e)) => json!({ "error": e.to_string() }),
+                None => Value::Null,
+            };
+            json!({ "instruction": format!("{:?}", instruction), "result": result })
+        })
+        .collect();
+
+    let logs: Vec<Value> = receipt
+        .logs
+        .iter()
+        .map(|(level, message)| json!({ "level": format!("{:?}", level), "message": message }))
+        .collect();
+
+    json!({
+        "success": receipt.success,
+        "execution_time": receipt.execution_time.map(|t| t as u64),
+        "instructions": instructions,
+        "logs": logs,
+        "new_entities": {
+            "packages": to_strings(packages),
+            "components": to_strings(components),
+            "resources": to_strings(resources),
+        },
+    })
+}
+
+/// Names of the blueprints in a package's code, each one exports an `<name>_abi` function.
+pub fn blueprint_names(code: &[u8]) -> Result<Vec<String>, String> {
+    let module: parity_wasm::elements::Module = parity_wasm::deserialize_buffer(code)
+        .map_err(|e| format!("Can't read package code: {}", e))?;
+
+    let mut names: Vec<String> = module
+        .export_section()
+        .map(|section| {
+            section
+                .entries()
+                .iter()
+                .filter(|export| matches!(export.internal(), Internal::Function(_)))
+                .filter_map(|export| export.field().strip_suffix("_abi"))
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    Ok(names)
+}
+
+/// Runs a transaction, when `trace` is set the trace lines are captured and returned as well.
+pub fn run_transaction<L: Ledger>(
+    executor: &mut TransactionExecutor<L>,
+    transaction: Transaction,
+    trace: bool,
+) -> (Option<Receipt>, Option<Vec<String>>) {
+    if !trace {
+        return (executor.run(transaction, false).ok(), None);
+    }
+
+    trace::capture(|| executor.run(transaction, true).ok())
+}
@@ -0,0 +1,346 @@
+use super::json_rpc_thread;
+use super::ledger::{FileLedger, RpcLedger};
+use super::setup;
+use super::snapshot::Snapshot;
+use super::state::{ServerState, State};
+use super::trace;
+
+use jsonrpc_core::{IoHandler, MetaIoHandler};
+use jsonrpc_http_server::{AccessControlAllowOrigin, DomainsValidation, ServerBuilder};
+use jsonrpc_pubsub::{PubSubHandler, Session};
+
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+// How often a free port for the TCP server is picked before giving up
+const TCP_BIND_ATTEMPTS: u32 = 5;
+
+/// Builds and starts a rad-rpc server, the binary is a thin wrapper around it.
+///
+/// ```ignore
+/// let server = RadRpcServer::new()
+///     .http("127.0.0.1:0".parse().unwrap())
+///     .ws(None)
+///     .tcp(None)
+///     .start()?;
+/// let url = format!("http://{}", server.http_address());
+/// server.shutdown();
+/// ```
+///
+/// Every server gets its own ledger, history and snapshots, so several can run in one process.
+/// Once one traces, stdout of the whole process is routed through rad-rpc so the engine's trace can
+/// be captured; anything else printed to stdout then ends up on stderr.
+pub struct RadRpcServer {
+    ledger: Option<RpcLedger>,
+    ledger_dir: Option<PathBuf>,
+    setup_file: Option<PathBuf>,
+    epoch: u64,
+    nonce: u64,
+    trace: bool,
+    http: SocketAddr,
+    threads: usize,
+    cors_origins: Vec<String>,
+    ws: Option<SocketAddr>,
+    tcp: Option<SocketAddr>,
+    ipc: Option<String>,
+}
+
+impl Default for RadRpcServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RadRpcServer {
+    /// HTTP on 127.0.0.1:3030, WebSocket on 3031 and TCP on 3032, with a freshly bootstrapped
+    /// ledger and no setup file.
+    pub fn new() -> RadRpcServer {
+        RadRpcServer {
+            ledger: None,
+            ledger_dir: None,
+            setup_file: None,
+            epoch: 0,
+            nonce: 0,
+            trace: false,
+            http: "127.0.0.1:3030".parse().unwrap(),
+            threads: 4,
+            cors_origins: vec!["*".to_string()],
+            ws: Some("127.0.0.1:3031".parse().unwrap()),
+            tcp: Some("127.0.0.1:3032".parse().unwrap()),
+            ipc: None,
+        }
+    }
+
+    /// Starts from this ledger instead of a freshly bootstrapped one.
+    pub fn ledger(mut self, ledger: RpcLedger) -> Self {
+        self.ledger = Some(ledger);
+        self
+    }
+
+    /// Keeps the ledger on disk in `dir`, resuming from it and skipping the setup if it has state.
+    pub fn ledger_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.ledger_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Runs the packages and manifests of this Config.json before serving.
+    pub fn setup_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.setup_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Epoch the transactions run in, a resumed ledger directory keeps the one it was left at.
+    pub fn epoch(mut self, epoch: u64) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    /// Nonce to start from, a resumed ledger directory keeps the one it was left at.
+    pub fn nonce(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self
+    }
+
+    /// Traces every transaction that doesn't say whether it wants to be traced.
+    pub fn trace(mut self, trace: bool) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// Address of the HTTP server, port 0 picks a free one.
+    pub fn http(mut self, address: SocketAddr) -> Self {
+        self.http = address;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Origins allowed to call the HTTP server, `*` allows any.
+    pub fn cors_origins(mut self, origins: Vec<String>) -> Self {
+        self.cors_origins = origins;
+        self
+    }
+
+    /// Address of the WebSocket server, None turns it off.
+    pub fn ws(mut self, address: Option<SocketAddr>) -> Self {
+        self.ws = address;
+        self
+    }
+
+    /// Address of the line delimited TCP server, None turns it off.
+    pub fn tcp(mut self, address: Option<SocketAddr>) -> Self {
+        self.tcp = address;
+        self
+    }
+
+    /// Path of the Unix domain socket, None turns it off.
+    pub fn ipc(mut self, path: Option<String>) -> Self {
+        self.ipc = path;
+        self
+    }
+
+    /// Prepares the ledger and starts all transports, returns once they are listening.
+    pub fn start(self) -> std::io::Result<ServerHandle> {
+        let state = self.prepare()?;
+
+        let mut io = IoHandler::new();
+        json_rpc_thread::add_methods(&mut io, &state);
+
+        // The WebSocket server gets the same methods, plus the subscriptions that need a session
+        let ws = match self.ws {
+            Some(address) => {
+                let mut ws_io = PubSubHandler::new(MetaIoHandler::default());
+                json_rpc_thread::add_methods(&mut ws_io, &state);
+                json_rpc_thread::add_subscriptions(&mut ws_io, &state);
+
+                let server = jsonrpc_ws_server::ServerBuilder::with_meta_extractor(
+                    ws_io,
+                    |context: &jsonrpc_ws_server::RequestContext| {
+                        Arc::new(Session::new(context.sender()))
+                    },
+                )
+                .start(&address)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+                Some(server)
+            }
+            None => None,
+        };
+
+        // Line delimited JSON-RPC over TCP, for backend services
+        let tcp = match self.tcp {
+            Some(address) => Some(start_tcp(&io, address)?),
+            None => None,
+        };
+
+        // Unix domain socket for local tooling, so parallel instances don't need a port each
+        let ipc = match &self.ipc {
+            Some(path) => Some(jsonrpc_ipc_server::ServerBuilder::new(io.clone()).start(path)?),
+            None => None,
+        };
+
+        let origins = self
+            .cors_origins
+            .iter()
+            .map(|origin| AccessControlAllowOrigin::from(origin.as_str()))
+            .collect();
+
+        let http = ServerBuilder::new(io)
+            .threads(self.threads)
+            .cors(DomainsValidation::AllowOnly(origins))
+            .start_http(&self.http)?;
+
+        Ok(ServerHandle {
+            http,
+            ws,
+            tcp,
+            ipc,
+            ipc_path: self.ipc,
+        })
+    }
+
+    /// Prepares the ledger and serves JSON-RPC over stdin and stdout until stdin closes.
+    pub fn serve_stdio(self) -> std::io::Result<()> {
+        let state = self.prepare()?;
+        json_rpc_thread::stdio_thread(&state);
+        Ok(())
+    }
+
+    /// Creates the state of the server and sets up its ledger.
+    fn prepare(&self) -> std::io::Result<State> {
+        // Tracing by default needs stdout from the start, otherwise the first traced call takes it
+        if self.trace {
+            trace::install();
+        }
+
+        let ledger = match &self.ledger {
+            Some(ledger) => ledger.clone(),
+            None => RpcLedger::with_bootstrap(),
+        };
+        let state = ServerState::new(ledger);
+        {
+            let mut config = state.config.write();
+            config.trace.store(self.trace, Ordering::SeqCst);
+            // Nothing is persisted yet, so this can't fail
+            let _ = config.restore_nonce(self.epoch, self.nonce);
+        }
+
+        // Keep the ledger on disk when a directory is given, and resume from it if it has state
+        let mut resumed = false;
+        if let Some(dir) = &self.ledger_dir {
+            eprintln!("Using ledger stored in {}", dir.display());
+            let file_ledger = FileLedger::with_bootstrap(dir)?;
+            *state.ledger.write() = RpcLedger::File(file_ledger);
+            resumed = state.config.write().persist_to(dir.join("config.json"))?;
+            state
+                .history
+                .write()
+                .persist_to(dir.join("history.jsonl"))?;
+            state.registry.write().persist_to(dir.join("names.json"))?;
+        }
+
+        if resumed {
+            eprintln!("Resuming previous ledger state, skipping setup");
+        } else if let Some(path) = &self.setup_file {
+            setup::run_setup(&state, path)?;
+        }
+
+        // Keep an in memory copy around for sandboxes that start from the set up ledger
+        let (epoch, nonce) = state.config.read().load_nonce();
+        let ledger = state.ledger.read().scratch();
+        let history = state.history.read().scratch();
+        let registry = state.registry.read().scratch();
+        *state.setup.write() = Some(Snapshot::new(ledger, epoch, nonce, history, registry));
+        Ok(state)
+    }
+}
+
+/// A running server, the transports stop when it is shut down.
+pub struct ServerHandle {
+    http: jsonrpc_http_server::Server,
+    ws: Option<jsonrpc_ws_server::Server>,
+    tcp: Option<(jsonrpc_tcp_server::Server, SocketAddr)>,
+    ipc: Option<jsonrpc_ipc_server::Server>,
+    ipc_path: Option<String>,
+}
+
+impl ServerHandle {
+    pub fn http_address(&self) -> SocketAddr {
+        *self.http.address()
+    }
+
+    pub fn ws_address(&self) -> Option<SocketAddr> {
+        self.ws.as_ref().map(|ws| *ws.addr())
+    }
+
+    pub fn tcp_address(&self) -> Option<SocketAddr> {
+        self.tcp.as_ref().map(|(_, address)| *address)
+    }
+
+    pub fn ipc_path(&self) -> Option<&str> {
+        self.ipc_path.as_deref()
+    }
+
+    /// Blocks until the HTTP server stops, then stops the other transports.
+    pub fn wait(self) {
+        let ServerHandle {
+            http, ws, tcp, ipc, ..
+        } = self;
+        http.wait();
+        close_others(ws, tcp, ipc);
+    }
+
+    pub fn shutdown(self) {
+        let ServerHandle {
+            http, ws, tcp, ipc, ..
+        } = self;
+        http.close();
+        close_others(ws, tcp, ipc);
+    }
+}
+
+/// Starts the TCP server and returns it with the address it listens on. The server doesn't tell
+/// which port it got, so for port 0 a free one is picked up front. Should something else take
+/// that port before the server binds it, another one is picked.
+fn start_tcp(
+    io: &IoHandler,
+    address: SocketAddr,
+) -> std::io::Result<(jsonrpc_tcp_server::Server, SocketAddr)> {
+    if address.port() != 0 {
+        let server = jsonrpc_tcp_server::ServerBuilder::new(io.clone()).start(&address)?;
+        return Ok((server, address));
+    }
+
+    let mut attempts = 1;
+    loop {
+        let free = TcpListener::bind(address)?.local_addr()?;
+        match jsonrpc_tcp_server::ServerBuilder::new(io.clone()).start(&free) {
+            Ok(server) => return Ok((server, free)),
+            Err(e) if e.kind() == ErrorKind::AddrInUse && attempts < TCP_BIND_ATTEMPTS => {
+                attempts += 1
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn close_others(
+    ws: Option<jsonrpc_ws_server::Server>,
+    tcp: Option<(jsonrpc_tcp_server::Server, SocketAddr)>,
+    ipc: Option<jsonrpc_ipc_server::Server>,
+) {
+    if let Some(ws) = ws {
+        ws.close();
+    }
+    if let Some((tcp, _)) = tcp {
+        tcp.close();
+    }
+    if let Some(ipc) = ipc {
+        ipc.close();
+    }
+}
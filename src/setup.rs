@@ -1,16 +1,21 @@
-use super::config::Config;
+use super::export::{Addresses, ExportSetup};
+use super::manifest;
+use super::manifest_parser;
+use super::registry::{self, Registry};
 use super::scrypto_helpers;
-use super::CONFIG;
-use super::LEDGER;
+use super::state::ServerState;
 
-use parking_lot::{RwLock, RwLockWriteGuard};
+use parking_lot::RwLockWriteGuard;
 
 use radix_engine::ledger::*;
 use radix_engine::transaction::*;
 use scrypto::prelude::*;
 
 use std::collections::HashMap;
-use std::sync::{atomic, Arc};
+use std::io;
+use std::iter;
+use std::path::Path;
+use std::sync::atomic;
 
 #[derive(serde::Serialize, serde::Deserialize)]
 struct PackageSetup {
@@ -45,18 +50,87 @@ impl PackageSetup {
     }
 }
 
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ManifestSetup {
+    path: String,
+    #[serde(default)]
+    components: Vec<String>,
+    #[serde(default)]
+    resources: Vec<String>,
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 struct PackagesSetup {
     packages: Vec<PackageSetup>,
+    // Text manifests that run in order once all packages are set up
+    #[serde(default)]
+    manifests: Vec<ManifestSetup>,
+    // Where to push the addresses once everything is set up
+    #[serde(default)]
+    export: ExportSetup,
 }
 
-pub fn run_setup() {
-    // Everything in the setup uses unwrap() hard failure is the desired outcome
+impl PackagesSetup {
+    /// Every name the setup gives out, they all end up in the registry.
+    fn names(&self) -> impl Iterator<Item = &String> {
+        let packages = self
+            .packages
+            .iter()
+            .flat_map(|p| iter::once(&p.name).chain(&p.components).chain(&p.resources));
+        let manifests = self
+            .manifests
+            .iter()
+            .flat_map(|m| m.components.iter().chain(&m.resources));
+        packages.chain(manifests)
+    }
+}
 
-    println!("Setting up ledger");
-    // Load Config.json
-    let package_file = std::fs::File::open("Config.json").unwrap();
-    let json: PackagesSetup = jsonrpc_core::serde_json::from_reader(package_file).unwrap();
+/// Reads Config.json, a name that can't be registered is rejected before anything runs.
+fn load_setup(path: &Path) -> io::Result<PackagesSetup> {
+    let error = |kind, message: String| {
+        io::Error::new(kind, format!("Config {}: {}", path.display(), message))
+    };
+
+    let file = std::fs::File::open(path).map_err(|e| error(e.kind(), e.to_string()))?;
+    let json: PackagesSetup = jsonrpc_core::serde_json::from_reader(file)
+        .map_err(|e| error(io::ErrorKind::InvalidData, e.to_string()))?;
+    if let Some(name) = json.names().find(|name| !registry::is_valid_name(name)) {
+        let message = format!(
+            "Name {} must only use letters, digits and underscores",
+            name
+        );
+        return Err(error(io::ErrorKind::InvalidData, message));
+    }
+    Ok(json)
+}
+
+/// Names an address of the setup. The names were checked when Config.json was loaded, so only
+/// saving them can fail, which doesn't stop the setup.
+fn register(registry: &mut Registry, name: &str, address: Address) {
+    if let Err(e) = registry.register(name, address) {
+        eprintln!("{}", e);
+    }
+}
+
+/// Runs a setup transaction, logging its trace to stderr so stdout stays free for stdio clients.
+fn run_logged<L: Ledger>(
+    executor: &mut TransactionExecutor<L>,
+    transaction: Transaction,
+    trace: bool,
+) -> Receipt {
+    let (receipt, trace) = scrypto_helpers::run_transaction(executor, transaction, trace);
+    for line in trace.unwrap_or_default() {
+        eprintln!("{}", line);
+    }
+    receipt.unwrap()
+}
+
+pub fn run_setup(state: &ServerState, path: &Path) -> io::Result<()> {
+    eprintln!("Setting up ledger");
+    let json = load_setup(path)?;
+
+    // Past loading Config.json everything in the setup uses unwrap(), hard failure is the desired
+    // outcome
 
     // Create admin account, dummy values
     let mut admin_key: Address =
@@ -64,18 +138,28 @@ pub fn run_setup() {
     let mut admin_account: Address =
         Address::from_str("02b9f7c0c44a6e2162403cea3fa44500dff50eb18fd4ff5a9dd079").unwrap();
 
-    let writer_lock = LEDGER.write();
+    // Filled once everything is set up, and exported after the locks are released
+    let mut addresses = None;
+
+    // Like every transaction, take the config before the ledger and hold it until the nonce is
+    // stored with the ledger
+    let mut config = state.config.write();
+    let writer_lock = state.ledger.write();
+
+    // With tracing on, the trace of every setup transaction is logged to stderr
+    let trace = config.trace.load(atomic::Ordering::SeqCst);
+    let (epoch, nonce) = config.load_nonce();
 
     // We use a closure to access the inner of an RwLock, notice we return the &mut ledger
     // at the very end to put it back in the RwLock, ONLY CALL THIS WHEN YOU HAVE ALREADY ACQUIRED
     // THE LOCK
     let mapped = RwLockWriteGuard::map(writer_lock, |ledger| {
-        let mut executor = TransactionExecutor::new(ledger, 0, 0);
+        let mut executor = TransactionExecutor::new(ledger, epoch, nonce);
 
         //Create admin account real values
         admin_key = executor.new_public_key();
         admin_account = executor.new_account(admin_key);
-        println!(
+        eprintln!(
             "admin_key: {} \n admin_account: {}",
             &admin_key, &admin_account
         );
@@ -96,11 +180,11 @@ pub fn run_setup() {
                 .build(signers)
                 .unwrap();
 
-            let receipt = executor.run(transaction, false).unwrap();
+            let receipt = run_logged(&mut executor, transaction, trace);
 
             // Grab the package
             let package_address = receipt.package(0).unwrap();
-            println!("Package {} published: {:?}", &pack.name, package_address);
+            eprintln!("Package {} published: {:?}", &pack.name, package_address);
             packages_hm.insert(pack.name.to_owned(), package_address.to_string());
 
             // Call the function new on the packages where the bool is enabled, all those that
@@ -122,43 +206,120 @@ pub fn run_setup() {
                 .build(vec![admin_key])
                 .unwrap();
 
-            let receipt = executor.run(transaction, false).unwrap();
+            let receipt = run_logged(&mut executor, transaction, trace);
 
-            let (resources, components, _) = scrypto_helpers::get_call_results(receipt);
+            let (resources, components, _) = scrypto_helpers::get_call_results(&receipt);
             assert_eq!(resources.len(), pack.resources.len());
             assert_eq!(components.len(), pack.components.len());
 
             // Push everything to hashmap to store in db
             for (address, name) in resources.iter().zip(pack.resources) {
-                println!("Resource {} instantiated: {:?}", &name, &address);
+                eprintln!("Resource {} instantiated: {:?}", &name, &address);
                 resources_hm.insert(name.to_owned(), address.to_string());
             }
 
             for (address, name) in components.iter().zip(pack.components) {
-                println!("Component {} instantiated: {:?}", name, address);
+                eprintln!("Component {} instantiated: {:?}", name, address);
                 components_hm.insert(name.to_owned(), address.to_string());
             }
         }
 
-        push_all_serverless(
-            resources_hm,
+        // Name everything so far, the manifests can refer to it by @name too
+        let mut registry = state.registry.write();
+        for (name, address) in packages_hm
+            .iter()
+            .chain(components_hm.iter())
+            .chain(resources_hm.iter())
+        {
+            register(&mut registry, name, Address::from_str(address).unwrap());
+        }
+        register(&mut registry, "admin_account", admin_account);
+        register(&mut registry, "admin_key", admin_key);
+        drop(registry);
+
+        // The manifests can refer to everything named so far, and to the admin account
+        for setup in json.manifests {
+            let mut variables: HashMap<String, String> = HashMap::new();
+            variables.extend(packages_hm.clone());
+            variables.extend(components_hm.clone());
+            variables.extend(resources_hm.clone());
+            variables.insert("admin_account".to_owned(), admin_account.to_string());
+            variables.insert("admin_key".to_owned(), admin_key.to_string());
+
+            let text = std::fs::read_to_string(&setup.path)
+                .unwrap_or_else(|e| panic!("{}: {}", &setup.path, e));
+            let steps = manifest_parser::parse_manifest(&text, &variables, &state.registry.read())
+                .unwrap_or_else(|e| panic!("{}: {}", &setup.path, e));
+            let transaction = manifest::build_transaction(&executor, steps, vec![admin_key])
+                .unwrap_or_else(|e| {
+                    panic!("{}: can't build the transaction: {:?}", &setup.path, e)
+                });
+
+            let receipt = run_logged(&mut executor, transaction, trace);
+            assert!(receipt.success, "Manifest {} failed", &setup.path);
+            eprintln!("Manifest {} ran", &setup.path);
+
+            let (resources, components, _) = scrypto_helpers::get_call_results(&receipt);
+            assert_eq!(
+                resources.len(),
+                setup.resources.len(),
+                "Manifest {} created another number of resources",
+                &setup.path
+            );
+            assert_eq!(
+                components.len(),
+                setup.components.len(),
+                "Manifest {} created another number of components",
+                &setup.path
+            );
+
+            // Named right away, so the next manifests can refer to them
+            let mut registry = state.registry.write();
+            for (address, name) in resources.iter().zip(setup.resources) {
+                eprintln!("Resource {} instantiated: {:?}", &name, &address);
+                register(&mut registry, &name, *address);
+                resources_hm.insert(name.to_owned(), address.to_string());
+            }
+
+            for (address, name) in components.iter().zip(setup.components) {
+                eprintln!("Component {} instantiated: {:?}", name, address);
+                register(&mut registry, &name, *address);
+                components_hm.insert(name.to_owned(), address.to_string());
+            }
+        }
+
+        addresses = Some(Addresses::new(
+            packages_hm,
             components_hm,
+            resources_hm,
             admin_account.to_string(),
             admin_key.to_string(),
-        );
+        ));
 
-        let write = CONFIG.write();
-        let _ = RwLockWriteGuard::map(write, |config| {
-            config.store_nonce(&executor);
-            config
-        });
+        // The whole setup is stored as one unit, a crash halfway runs it again on the next start
+        let nonce = executor.nonce();
+        drop(executor);
+        ledger
+            .commit(epoch, nonce)
+            .unwrap_or_else(|e| panic!("Can't store the set up ledger: {}", e));
+        if let Err(e) = config.store_nonce(nonce) {
+            eprintln!("Can't persist the nonce: {}", e);
+        }
 
         //Pass back the ledger to the RwLockWriteGuard
         ledger
     });
+    drop(mapped);
+    drop(config);
+
+    // A slow webhook must not keep the ledger locked
+    if let Some(addresses) = addresses {
+        push_all_serverless(&json.export, &addresses);
+    }
+    Ok(())
 }
 
-pub fn create_setup_file_example() {
+pub fn create_setup_file_example(path: &Path) {
     let mut packages: Vec<PackageSetup> = Vec::new();
     let package = PackageSetup::new(
         "GumballMachine",
@@ -181,16 +342,22 @@ pub fn create_setup_file_example() {
 
     packages.push(package);
 
-    let example = PackagesSetup { packages };
+    let example = PackagesSetup {
+        packages,
+        manifests: Vec::new(),
+        export: ExportSetup::default(),
+    };
 
-    let f = std::fs::File::create("Config.json").unwrap();
+    let f = std::fs::File::create(path).unwrap();
     let _ = jsonrpc_core::serde_json::to_writer_pretty(f, &example);
 }
 
-fn push_all_serverless(
-    resources_hm: HashMap<String, String>,
-    components_hm: HashMap<String, String>,
-    admin_account: String,
-    admin_key: String,
-) {
+/// Pushes the addresses to every sink in the export section, so frontends pick up the fresh
+/// addresses after every restart. The server is usable without them, so failures are only logged.
+fn push_all_serverless(export: &ExportSetup, addresses: &Addresses) {
+    for sink in export.sinks() {
+        if let Err(e) = sink.push(addresses) {
+            eprintln!("Exporting addresses failed: {}", e);
+        }
+    }
 }
@@ -0,0 +1,77 @@
+use super::history::History;
+use super::ledger::RpcLedger;
+use super::registry::Registry;
+
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A full copy of the ledger together with the epoch and nonce it was taken at, and the history
+/// and names that go with that ledger.
+pub struct Snapshot {
+    pub ledger: RpcLedger,
+    pub epoch: u64,
+    pub nonce: u64,
+    // The history as it was, restoring puts it back in place of the current one
+    pub history: History,
+    pub registry: Registry,
+    // Seconds since the unix epoch
+    pub created: u64,
+}
+
+impl Snapshot {
+    pub fn new(
+        ledger: RpcLedger,
+        epoch: u64,
+        nonce: u64,
+        history: History,
+        registry: Registry,
+    ) -> Snapshot {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        Snapshot {
+            ledger,
+            epoch,
+            nonce,
+            history,
+            registry,
+            created,
+        }
+    }
+}
+
+pub struct Snapshots {
+    next_id: u64,
+    snapshots: BTreeMap<u64, Snapshot>,
+}
+
+impl Snapshots {
+    pub fn new() -> Snapshots {
+        Snapshots {
+            next_id: 0,
+            snapshots: BTreeMap::new(),
+        }
+    }
+
+    /// Stores a snapshot and returns its id, ids are never reused.
+    pub fn create(&mut self, snapshot: Snapshot) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.snapshots.insert(id, snapshot);
+        id
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Snapshot> {
+        self.snapshots.get(&id)
+    }
+
+    pub fn delete(&mut self, id: u64) -> bool {
+        self.snapshots.remove(&id).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&u64, &Snapshot)> {
+        self.snapshots.iter()
+    }
+}
@@ -0,0 +1,45 @@
+use super::config::Config;
+use super::history::History;
+use super::ledger::RpcLedger;
+use super::registry::Registry;
+use super::sandbox::Sandboxes;
+use super::snapshot::{Snapshot, Snapshots};
+use super::subscriptions::Subscriptions;
+
+use parking_lot::RwLock;
+use std::sync::Arc;
+
+/// Everything one server works on, shared by all of its handlers and transports. Servers with
+/// their own state are completely independent, also when they run in the same process.
+///
+/// When more than one lock is needed they are always taken in field order, so the nonce never
+/// goes out of sync with the ledger it belongs to.
+pub struct ServerState {
+    pub config: RwLock<Config>,
+    pub ledger: RwLock<RpcLedger>,
+    pub history: RwLock<History>,
+    pub snapshots: RwLock<Snapshots>,
+    pub subscriptions: RwLock<Subscriptions>,
+    pub registry: RwLock<Registry>,
+    // The server as it was once it was set up, new sandboxes can start from it
+    pub setup: RwLock<Option<Snapshot>>,
+    // Only the main server has sandboxes, a sandbox never has any of its own
+    pub sandboxes: RwLock<Sandboxes>,
+}
+
+pub type State = Arc<ServerState>;
+
+impl ServerState {
+    pub fn new(ledger: RpcLedger) -> State {
+        Arc::new(ServerState {
+            config: RwLock::new(Config::new()),
+            ledger: RwLock::new(ledger),
+            history: RwLock::new(History::new()),
+            snapshots: RwLock::new(Snapshots::new()),
+            subscriptions: RwLock::new(Subscriptions::new()),
+            registry: RwLock::new(Registry::new()),
+            setup: RwLock::new(None),
+            sandboxes: RwLock::new(Sandboxes::new()),
+        })
+    }
+}
@@ -0,0 +1,78 @@
+use jsonrpc_core::serde_json::json;
+use jsonrpc_core::{Params, Value};
+use jsonrpc_pubsub::{Sink, Subscriber, SubscriptionId};
+use scrypto::prelude::*;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Shared by all servers and sandboxes, so an id alone says which subscription to drop
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// What a WebSocket client subscribed to.
+#[derive(Clone, PartialEq)]
+pub enum Topic {
+    Transactions,
+    Logs,
+    Component(Address),
+}
+
+pub struct Subscriptions {
+    sinks: HashMap<u64, (Topic, Sink)>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Subscriptions {
+        Subscriptions {
+            sinks: HashMap::new(),
+        }
+    }
+
+    /// Gives the subscriber its id and starts sending it notifications for the topic.
+    pub fn subscribe(&mut self, topic: Topic, subscriber: Subscriber) {
+        let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+        if let Ok(sink) = subscriber.assign_id(SubscriptionId::Number(id)) {
+            self.sinks.insert(id, (topic, sink));
+        }
+    }
+
+    pub fn unsubscribe(&mut self, id: &SubscriptionId) -> bool {
+        match id {
+            SubscriptionId::Number(id) => self.sinks.remove(id).is_some(),
+            SubscriptionId::String(_) => false,
+        }
+    }
+
+    /// All components somebody is watching.
+    pub fn components(&self) -> Vec<Address> {
+        let mut components: Vec<Address> = Vec::new();
+        for (topic, _) in self.sinks.values() {
+            if let Topic::Component(address) = topic {
+                if !components.contains(address) {
+                    components.push(*address);
+                }
+            }
+        }
+        components
+    }
+
+    /// Sends `result` to everyone subscribed to `topic`, dropping clients that went away.
+    pub fn notify(&mut self, topic: &Topic, result: Value) {
+        let mut closed = Vec::new();
+        for (id, (t, sink)) in &self.sinks {
+            if t != topic {
+                continue;
+            }
+            let mut params = jsonrpc_core::serde_json::Map::new();
+            params.insert("subscription".to_string(), json!(id));
+            params.insert("result".to_string(), result.clone());
+            if sink.notify(Params::Map(params)).is_err() {
+                closed.push(*id);
+            }
+        }
+
+        for id in closed {
+            self.sinks.remove(&id);
+        }
+    }
+}
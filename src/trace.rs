@@ -0,0 +1,180 @@
+//! The engine prints its trace straight to stdout. Once a server that traces by default starts, or
+//! the first traced transaction runs, stdout is pointed at a pipe for the rest of the process. A reader thread hands every line to the traced transaction
+//! that is running, or passes it on to stderr when none is. The JSON-RPC responses of `--stdio`
+//! go to a copy of the original stdout, so nothing the engine prints can end up in that stream.
+
+use parking_lot::{const_mutex, Condvar, Mutex};
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Where the lines printed to stdout go while a traced transaction runs.
+struct Sink {
+    lines: Vec<String>,
+    // Printed once the transaction is done, everything before it belongs to the transaction
+    end_marker: String,
+    done: bool,
+}
+
+enum Routing {
+    NotInstalled,
+    // The copy of the original stdout
+    Routed(File),
+    // Stdout is left alone, traces can't be captured
+    Unavailable,
+}
+
+// Traced transactions take turns, the lines can't be told apart otherwise
+static TRACE_LOCK: Mutex<()> = const_mutex(());
+static SINK: Mutex<Option<Sink>> = const_mutex(None);
+static SINK_DONE: Condvar = Condvar::new();
+static ROUTING: Mutex<Routing> = const_mutex(Routing::NotInstalled);
+static NEXT_MARKER: AtomicU64 = AtomicU64::new(0);
+
+/// Routes stdout through the reader thread, only the first call does anything.
+pub fn install() {
+    let mut routing = ROUTING.lock();
+    if !matches!(*routing, Routing::NotInstalled) {
+        return;
+    }
+    *routing = match route_stdout() {
+        Ok(original) => Routing::Routed(original),
+        Err(e) => {
+            eprintln!("Traces can't be captured: {}", e);
+            Routing::Unavailable
+        }
+    };
+}
+
+/// Writes a JSON-RPC response to the real stdout, whatever the engine does with its stdout.
+pub fn write_response(response: &str) {
+    match &mut *ROUTING.lock() {
+        Routing::Routed(original) => {
+            let _ = writeln!(original, "{}", response);
+            let _ = original.flush();
+        }
+        _ => {
+            let mut stdout = std::io::stdout();
+            let _ = writeln!(stdout, "{}", response);
+            let _ = stdout.flush();
+        }
+    }
+}
+
+/// Runs `f` and returns what it printed to stdout, line by line, or None if stdout can't be
+/// routed. Stdout belongs to the whole process, so lines other threads print while `f` runs are
+/// picked up as well.
+pub fn capture<T, F: FnOnce() -> T>(f: F) -> (T, Option<Vec<String>>) {
+    install();
+    if !matches!(*ROUTING.lock(), Routing::Routed(_)) {
+        return (f(), None);
+    }
+
+    let _guard = TRACE_LOCK.lock();
+    let end_marker = format!(
+        "rad-rpc-trace-end-{}",
+        NEXT_MARKER.fetch_add(1, Ordering::SeqCst)
+    );
+    *SINK.lock() = Some(Sink {
+        lines: Vec::new(),
+        end_marker: end_marker.clone(),
+        done: false,
+    });
+
+    let result = f();
+
+    let mut stdout = std::io::stdout();
+    let _ = writeln!(stdout, "{}", end_marker);
+    let _ = stdout.flush();
+
+    // Wait for the reader thread to get to the marker, but don't hang if it never does
+    let mut sink = SINK.lock();
+    while sink.as_ref().map_or(false, |s| !s.done) {
+        if SINK_DONE
+            .wait_for(&mut sink, Duration::from_secs(1))
+            .timed_out()
+        {
+            break;
+        }
+    }
+    let lines = sink.take().map(|s| s.lines);
+    (result, lines)
+}
+
+fn read_lines(pipe: File) {
+    for line in BufReader::new(pipe).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+
+        let mut sink = SINK.lock();
+        match &mut *sink {
+            Some(s) if s.done => eprintln!("{}", line),
+            Some(s) if line == s.end_marker => {
+                s.done = true;
+                SINK_DONE.notify_all();
+            }
+            Some(s) => s.lines.push(strip_ansi(&line)),
+            None => eprintln!("{}", line),
+        }
+    }
+}
+
+/// Points fd 1 at a pipe read by `read_lines` and returns a copy of the original stdout.
+#[cfg(unix)]
+fn route_stdout() -> std::io::Result<File> {
+    use std::os::unix::io::FromRawFd;
+
+    let _ = std::io::stdout().flush();
+    unsafe {
+        let original = libc::dup(1);
+        if original < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let original = File::from_raw_fd(original);
+
+        let mut fds = [0; 2];
+        if libc::pipe(fds.as_mut_ptr()) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let (read, write) = (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1]));
+        if libc::dup2(fds[1], 1) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // Fd 1 keeps the pipe open from here on
+        drop(write);
+
+        std::thread::spawn(move || read_lines(read));
+        Ok(original)
+    }
+}
+
+#[cfg(not(unix))]
+fn route_stdout() -> std::io::Result<File> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "stdout can only be routed on unix",
+    ))
+}
+
+/// Removes the terminal color codes the engine puts around its log levels.
+fn strip_ansi(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip up to and including the final byte of the escape sequence
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
@@ -0,0 +1,49 @@
+use jsonrpc_core::serde_json::{self, json, Value};
+use rad_rpc::RadRpcServer;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+
+fn request(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params })
+}
+
+#[test]
+fn serves_over_http_and_tcp_on_free_ports() {
+    let server = RadRpcServer::new()
+        .http("127.0.0.1:0".parse().unwrap())
+        .ws(None)
+        .tcp(Some("127.0.0.1:0".parse().unwrap()))
+        .epoch(5)
+        .nonce(100)
+        .start()
+        .unwrap();
+    let http = server.http_address();
+    let tcp = server.tcp_address().unwrap();
+    assert_ne!(http.port(), 0);
+    assert_ne!(tcp.port(), 0);
+
+    let body = ureq::post(&format!("http://{}", http))
+        .set("Content-Type", "application/json")
+        .send_string(&request("new_account", json!([])).to_string())
+        .unwrap()
+        .into_string()
+        .unwrap();
+    let response: Value = serde_json::from_str(&body).unwrap();
+    assert!(response["result"]["account"].is_string(), "{}", response);
+
+    // The account created over HTTP shows up over TCP, both serve the same ledger
+    let mut stream = TcpStream::connect(tcp).unwrap();
+    writeln!(stream, "{}", request("list_transactions", json!({}))).unwrap();
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line).unwrap();
+    let response: Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(response["result"]["total"], 1, "{}", response);
+
+    let transaction = &response["result"]["transactions"][0];
+    assert_eq!(transaction["kind"], "new_account");
+    assert_eq!(transaction["epoch"], 5);
+    assert_eq!(transaction["nonce"], 100);
+
+    server.shutdown();
+}